@@ -6,13 +6,38 @@ use std::fmt::{self};
 #[cfg(feature = "user")]
 use aya::programs::perf_event::PerfEventConfig;
 #[cfg(feature = "user")]
-use aya::programs::perf_event::{HardwareEvent, SoftwareEvent};
+use aya::programs::perf_event::{HardwareEvent, HwCacheEvent, HwCacheOp, HwCacheResult, SoftwareEvent};
 #[cfg(feature = "user")]
 use bincode_next::{Decode, Encode};
 
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
-pub const PERF_EVENT_VARIANTS: usize = 22;
+pub const PERF_EVENT_VARIANTS: usize = 113;
+
+// how many different functions can be uprobed at once, every slot has its own PerfEventType but they all
+// share the uprobe_entry/uprobe_return programs in the ebpf crate, which get the slot as the attach cookie
+pub const UPROBE_SLOTS: u8 = 8;
+
+// same thing for kprobes
+pub const KPROBE_SLOTS: u8 = 8;
+
+// and tracepoints, which are picked at runtime with -e tracepoint:category:name
+pub const TRACEPOINT_SLOTS: u8 = 8;
+
+// usdt probes go through uprobes too, but get their own slots since they also fetch an argument
+pub const USDT_SLOTS: u8 = 8;
+
+// raw pmu events (-e raw:0x1a2 or a vendor event name), the config is only known at runtime so they get slots too
+pub const RAW_SLOTS: u8 = 8;
+
+// bits of the ALLOW_FILTER map, each one turns on the allowlist map of the same name
+pub const ALLOW_TGIDS: u8 = 1 << 0;
+pub const ALLOW_UIDS: u8 = 1 << 1;
+pub const ALLOW_CGROUPS: u8 = 1 << 2;
+pub const ALLOW_COMMS: u8 = 1 << 3;
+
+// one UsdtArgSpec per attached probe site (a probe can show up in several places), indexed by the uprobe cookie
+pub const USDT_ARG_SPECS: u32 = 256;
 
 #[cfg(feature = "user")]
 #[derive(Debug)]
@@ -25,6 +50,10 @@ pub enum FlextraceError {
     NoPerfHwId(String),
     NoPerfSwId(String),
     UProbeAttachFailure(String),
+    KProbeAttachFailure(String),
+    TracepointAttachFailure(String),
+    UsdtAttachFailure(String),
+    BadSaveFormat(String),
     Msg(String),
 }
 
@@ -35,6 +64,11 @@ impl fmt::Display for FlextraceError {
             Self::TooManyEvents(ctx) => write!(f, "too many events for one process, max is {PERF_EVENT_VARIANTS}, context: {ctx}"),
             Self::BadArgument(ctx) => write!(f, "bad arguments lol, input was {ctx}"),
             Self::NoSuchPerfEventType(ctx) => write!(f, "the perf event type {ctx} does not exist or is not currently supported"),
+            Self::UProbeAttachFailure(ctx) => write!(f, "failed to attach uprobe: {ctx}"),
+            Self::KProbeAttachFailure(ctx) => write!(f, "failed to attach kprobe: {ctx}"),
+            Self::TracepointAttachFailure(ctx) => write!(f, "failed to attach tracepoint: {ctx}"),
+            Self::UsdtAttachFailure(ctx) => write!(f, "failed to attach usdt probe: {ctx}"),
+            Self::BadSaveFormat(ctx) => write!(f, "cant read this saved profile: {ctx}"),
             _ => write!(f, "whatever error this is i was too lazy to write an error msg for it"),
         }
     }
@@ -53,6 +87,115 @@ pub struct PerfSample {
     pub gid: u32,
     pub cmd: [u8; 16],
     pub stack_id: Option<i64>,
+    pub kernel_stack_id: Option<i64>,
+    pub timestamp: u64, // bpf_ktime_get_ns(), CLOCK_MONOTONIC
+    pub cpu: u32,
+    pub value: u64, // what this means depends on the event, ie latency in ns for uprobes, 0 for perf events
+    pub key: u64, // what the value belongs to for keyed events, ie the syscall number, block device or lock address. tcp fills it in userspace
+    pub failed: bool, // whether whatever got measured errored out, ie a syscall returning -errno
+    pub cgroup: u64, // cgroup v2 id of the task the sample belongs to
+    pub ns_pid: u32, // pid and tgid as the tasks own pid namespace sees them, 0 when we dont know
+    pub ns_tgid: u32,
+    pub socket: SocketTuple, // only filled in for tcp events
+}
+
+// local and remote end of a tcp connection, v4 addresses only use the first 4 bytes. ports are in host order
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "user", derive(Encode, Decode))]
+#[repr(C)]
+pub struct SocketTuple {
+    pub family: u16,
+    pub sport: u16,
+    pub dport: u16,
+    pub _pad: u16,
+    pub saddr: [u8; 16],
+    pub daddr: [u8; 16],
+}
+
+#[cfg(feature = "user")]
+const HW_CACHE_NAMES: [&str; 7] = ["l1d", "l1i", "ll", "dtlb", "itlb", "bpu", "node"];
+#[cfg(feature = "user")]
+const HW_CACHE_OP_NAMES: [&str; 3] = ["read", "write", "prefetch"];
+#[cfg(feature = "user")]
+const HW_CACHE_RESULT_NAMES: [&str; 2] = ["access", "miss"];
+
+pub const AF_INET: u16 = 2;
+pub const AF_INET6: u16 = 10;
+
+#[cfg(feature = "user")]
+impl SocketTuple {
+    pub fn local(&self) -> String {
+        Self::format(self.family, &self.saddr, self.sport)
+    }
+
+    pub fn remote(&self) -> String {
+        Self::format(self.family, &self.daddr, self.dport)
+    }
+
+    fn format(family: u16, addr: &[u8; 16], port: u16) -> String {
+        match family {
+            AF_INET => format!("{}:{port}", std::net::Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3])),
+            AF_INET6 => format!("[{}]:{port}", std::net::Ipv6Addr::from(*addr)),
+            _ => String::from("?"),
+        }
+    }
+}
+
+// how often a perf event takes a sample, every period events or about frequency times a second with the
+// kernel adjusting the period as it goes
+#[cfg(feature = "user")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum Sampling {
+    Period(u64),
+    Frequency(u64),
+}
+
+#[cfg(feature = "user")]
+impl Sampling {
+    // how many events one sample stands for, which is only fixed when sampling by period
+    pub fn period(&self) -> Option<u64> {
+        match self {
+            Self::Period(period) => Some(*period),
+            Self::Frequency(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "user")]
+impl fmt::Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Period(period) => write!(f, "every {period}"),
+            Self::Frequency(frequency) => write!(f, "{frequency}hz"),
+        }
+    }
+}
+
+// what a perf event counted (sampled or not), already scaled up for the time it spent multiplexed off
+// the pmu. enabled_ns is how long it was counting for, so value over that is the rate
+#[cfg(feature = "user")]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct EventCount {
+    pub value: u64,
+    pub enabled_ns: u64,
+}
+
+#[cfg(feature = "user")]
+impl EventCount {
+    pub fn per_second(&self) -> f64 {
+        if self.enabled_ns == 0 { return 0.0 }
+        self.value as f64 / (self.enabled_ns as f64 / 1e9)
+    }
+
+    // counters only ever go up, so whatever happened after an earlier reading of the same one
+    pub fn since(&self, earlier: &EventCount) -> EventCount {
+        EventCount { value: self.value.saturating_sub(earlier.value), enabled_ns: self.enabled_ns.saturating_sub(earlier.enabled_ns) }
+    }
+
+    pub fn merge(&mut self, other: &EventCount) {
+        self.value += other.value;
+        self.enabled_ns += other.enabled_ns;
+    }
 }
 
 // since we want to have one map for all types of perf events we'll use this internally
@@ -89,14 +232,241 @@ pub enum PerfEventType {
     AlignmentFaults = 18,
     EmulationFaults = 19,
     CgroupSwitches = 20,
+
+    //uprobes, one per slot
+    Uprobe0 = 23,
+    Uprobe1 = 24,
+    Uprobe2 = 25,
+    Uprobe3 = 26,
+    Uprobe4 = 27,
+    Uprobe5 = 28,
+    Uprobe6 = 29,
+    Uprobe7 = 30,
+
+    //kprobes, one per slot
+    Kprobe0 = 31,
+    Kprobe1 = 32,
+    Kprobe2 = 33,
+    Kprobe3 = 34,
+    Kprobe4 = 35,
+    Kprobe5 = 36,
+    Kprobe6 = 37,
+    Kprobe7 = 38,
+
+    //tracepoints, one per slot
+    Tracepoint0 = 39,
+    Tracepoint1 = 40,
+    Tracepoint2 = 41,
+    Tracepoint3 = 42,
+    Tracepoint4 = 43,
+    Tracepoint5 = 44,
+    Tracepoint6 = 45,
+    Tracepoint7 = 46,
+
+    //usdt probes, one per slot
+    Usdt0 = 47,
+    Usdt1 = 48,
+    Usdt2 = 49,
+    Usdt3 = 50,
+    Usdt4 = 51,
+    Usdt5 = 52,
+    Usdt6 = 53,
+    Usdt7 = 54,
+
+    //raw_syscalls:sys_enter/sys_exit, keyed by syscall number
+    Syscall = 55,
+
+    //block:block_rq_issue/block_rq_complete, keyed by device
+    BlockIo = 56,
+
+    //malloc/calloc/realloc/mmap returning, added up per stack in ebpf (AllocStats) instead of sent one by one
+    Alloc = 57,
+    //free/munmap of a block we saw getting allocated, counted against the stack that allocated it
+    Free = 58,
+    //the allocated bytes of a stack that havent been freed yet
+    LiveBytes = 59,
+
+    //time spent blocked in futex waits, keyed by the futex address
+    LockWait = 60,
+
+    //tcp traffic, keyed by connection. send and recv have the byte count as the value
+    TcpSend = 61,
+    TcpRecv = 62,
+    TcpRetransmit = 63,
+
+    //hardware cache events, cache x op x result in that order so hw_cache() can work them out
+    L1dReadAccess = 64,
+    L1dReadMiss = 65,
+    L1dWriteAccess = 66,
+    L1dWriteMiss = 67,
+    L1dPrefetchAccess = 68,
+    L1dPrefetchMiss = 69,
+    L1iReadAccess = 70,
+    L1iReadMiss = 71,
+    L1iWriteAccess = 72,
+    L1iWriteMiss = 73,
+    L1iPrefetchAccess = 74,
+    L1iPrefetchMiss = 75,
+    LlReadAccess = 76,
+    LlReadMiss = 77,
+    LlWriteAccess = 78,
+    LlWriteMiss = 79,
+    LlPrefetchAccess = 80,
+    LlPrefetchMiss = 81,
+    DtlbReadAccess = 82,
+    DtlbReadMiss = 83,
+    DtlbWriteAccess = 84,
+    DtlbWriteMiss = 85,
+    DtlbPrefetchAccess = 86,
+    DtlbPrefetchMiss = 87,
+    ItlbReadAccess = 88,
+    ItlbReadMiss = 89,
+    ItlbWriteAccess = 90,
+    ItlbWriteMiss = 91,
+    ItlbPrefetchAccess = 92,
+    ItlbPrefetchMiss = 93,
+    BpuReadAccess = 94,
+    BpuReadMiss = 95,
+    BpuWriteAccess = 96,
+    BpuWriteMiss = 97,
+    BpuPrefetchAccess = 98,
+    BpuPrefetchMiss = 99,
+    NodeReadAccess = 100,
+    NodeReadMiss = 101,
+    NodeWriteAccess = 102,
+    NodeWriteMiss = 103,
+    NodePrefetchAccess = 104,
+    NodePrefetchMiss = 105,
+
+    //raw pmu events, one per slot
+    Raw0 = 106,
+    Raw1 = 107,
+    Raw2 = 108,
+    Raw3 = 109,
+    Raw4 = 110,
+    Raw5 = 111,
+    Raw6 = 112,
+    Raw7 = 113,
+}
+
+// one bit per possible event id, which is the whole u8 range so new events never outgrow it
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct EventMask(pub [u64; 4]);
+
+impl EventMask {
+    pub fn all() -> Self {
+        EventMask([u64::MAX; 4])
+    }
+
+    pub fn set(&mut self, e_type: u8) {
+        self.0[(e_type >> 6) as usize] |= 1 << (e_type & 63);
+    }
+
+    pub fn contains(&self, e_type: u8) -> bool {
+        self.0[(e_type >> 6) as usize] & (1 << (e_type & 63)) != 0
+    }
 }
 
 #[derive(Copy, Clone)]
-pub struct PerfProcessConfig(pub u32, pub bool);
+pub struct PerfProcessConfig(pub EventMask, pub bool);
 
 #[cfg(feature = "user")]
 unsafe impl aya::Pod for PerfProcessConfig {}
 
+// allocations get added up in ebpf per thread and allocating stack instead of every malloc and free
+// going through the ring buffer, userspace reads the totals every so often and records whats new.
+// stack_id is negative when the stack wasnt wanted
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct AllocKey {
+    pub tgid: u32,
+    pub pid: u32,
+    pub stack_id: i64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for AllocKey {}
+
+// log2 buckets of allocation sizes, bucket i is [2^(i-1), 2^i) like the userspace Histogram and the
+// last one takes everything bigger
+pub const ALLOC_SIZE_BUCKETS: usize = 32;
+
+// running totals that only ever go up, frees count against the stack that allocated the block.
+// the rest is whatever we need to record the thread the first time it shows up
+#[derive(Default, Copy, Clone, Debug)]
+#[repr(C)]
+pub struct AllocStats {
+    pub allocs: u64,
+    pub bytes: u64,
+    pub frees: u64,
+    pub freed_bytes: u64,
+    pub max: u64,
+    pub sizes: [u32; ALLOC_SIZE_BUCKETS],
+    pub uid: u32,
+    pub gid: u32,
+    pub cgroup: u64,
+    pub ns_pid: u32,
+    pub ns_tgid: u32,
+    pub cmd: [u8; 16],
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for AllocStats {}
+
+// where ebpf finds the ids a task has in its own pid namespace, task->thread_pid->numbers[level].nr and
+// the same for task->group_leader. task_struct moves around between kernels and configs so userspace
+// fills this in from the kernels BTF, all zero means it couldnt
+#[derive(Default, Copy, Clone, Debug)]
+#[repr(C)]
+pub struct NsPidOffsets {
+    pub task_thread_pid: u32,
+    pub task_group_leader: u32,
+    pub pid_level: u32,
+    pub pid_numbers: u32,
+    pub upid_size: u32,
+    pub upid_nr: u32,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for NsPidOffsets {}
+
+// where struct sock_common keeps the connections addresses and ports, also from BTF. daddr really is at 0
+// so family being 0 is what means userspace couldnt find them. the v6 ones stay 0 on kernels without ipv6
+#[derive(Default, Copy, Clone, Debug)]
+#[repr(C)]
+pub struct SockOffsets {
+    pub daddr: u32,
+    pub rcv_saddr: u32,
+    pub dport: u32,
+    pub num: u32,
+    pub family: u32,
+    pub v6_daddr: u32,
+    pub v6_rcv_saddr: u32,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for SockOffsets {}
+
+pub const USDT_ARG_NONE: u8 = 0;
+pub const USDT_ARG_CONST: u8 = 1; // $value
+pub const USDT_ARG_REG: u8 = 2; // %reg
+pub const USDT_ARG_DEREF: u8 = 3; // value(%reg)
+
+// how to fetch one usdt argument, built from the arg spec string in the .note.stapsdt entry.
+// size is in bytes and negative for signed args, reg_offset is the register's offset into pt_regs
+#[derive(Default, Copy, Clone, Debug)]
+#[repr(C)]
+pub struct UsdtArgSpec {
+    pub kind: u8,
+    pub size: i8,
+    pub reg_offset: u16,
+    pub value: i64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for UsdtArgSpec {}
+
 #[cfg(feature = "user")]
 impl PerfEventType {
     pub fn from_str(thing: &String) -> Result<PerfEventType, FlextraceError> {
@@ -124,7 +494,16 @@ impl PerfEventType {
             "branch_instructions" => Ok(PerfEventType::BranchInstructions),
             "cache_references" => Ok(PerfEventType::CacheReferences),
             "instructions" => Ok(PerfEventType::Instructions),
-            _ => Err(FlextraceError::NoSuchPerfEventType(thing.to_owned())),
+            "syscalls" => Ok(PerfEventType::Syscall),
+            "block_io" => Ok(PerfEventType::BlockIo),
+            "alloc_bytes" => Ok(PerfEventType::Alloc),
+            "frees" => Ok(PerfEventType::Free),
+            "live_bytes" => Ok(PerfEventType::LiveBytes),
+            "lock_wait" => Ok(PerfEventType::LockWait),
+            "tcp_send" => Ok(PerfEventType::TcpSend),
+            "tcp_recv" => Ok(PerfEventType::TcpRecv),
+            "tcp_retransmit" => Ok(PerfEventType::TcpRetransmit),
+            _ => PerfEventType::hw_cache_from_str(thing).ok_or(FlextraceError::NoSuchPerfEventType(thing.to_owned())),
         }
     }
 
@@ -155,10 +534,194 @@ impl PerfEventType {
             Self::AlignmentFaults => Some(String::from("event_alignment_faults")),
             Self::EmulationFaults => Some(String::from("event_emulation_faults")),
             Self::CgroupSwitches => Some(String::from("event_cgroup_switches")),
+
+            // uprobes, there arent programs by these names since every slot runs uprobe_entry/uprobe_return
+            Self::Uprobe0 | Self::Uprobe1 | Self::Uprobe2 | Self::Uprobe3 |
+            Self::Uprobe4 | Self::Uprobe5 | Self::Uprobe6 | Self::Uprobe7 => Some(format!("uprobe_{}", self.slot()?)),
+
+            // kprobes, same deal with kretprobe_
+            Self::Kprobe0 | Self::Kprobe1 | Self::Kprobe2 | Self::Kprobe3 |
+            Self::Kprobe4 | Self::Kprobe5 | Self::Kprobe6 | Self::Kprobe7 => Some(format!("kprobe_{}", self.slot()?)),
+
+            // hw cache
+            _ if self.hw_cache().is_some() => {
+                let (cache, op, result) = self.hw_cache()?;
+                Some(format!("event_{}_{}_{}", HW_CACHE_NAMES[cache as usize], HW_CACHE_OP_NAMES[op as usize], HW_CACHE_RESULT_NAMES[result as usize]))
+            }
+
+            // tracepoints
+            Self::Tracepoint0 | Self::Tracepoint1 | Self::Tracepoint2 | Self::Tracepoint3 |
+            Self::Tracepoint4 | Self::Tracepoint5 | Self::Tracepoint6 | Self::Tracepoint7 => Some(format!("tracepoint_{}", self.slot()?)),
+
+            // usdt probes
+            Self::Usdt0 | Self::Usdt1 | Self::Usdt2 | Self::Usdt3 |
+            Self::Usdt4 | Self::Usdt5 | Self::Usdt6 | Self::Usdt7 => Some(format!("usdt_{}", self.slot()?)),
+
+            // raw pmu events, not event_ since "all" shouldnt pick them up
+            Self::Raw0 | Self::Raw1 | Self::Raw2 | Self::Raw3 |
+            Self::Raw4 | Self::Raw5 | Self::Raw6 | Self::Raw7 => Some(format!("raw_{}", self.slot()?)),
+            _ => None,
+        }
+    }
+
+    // which slot of its kind a slotted event (uprobes etc) is in
+    pub fn slot(&self) -> Option<u8> {
+        match self {
+            Self::Uprobe0 | Self::Uprobe1 | Self::Uprobe2 | Self::Uprobe3 |
+            Self::Uprobe4 | Self::Uprobe5 | Self::Uprobe6 | Self::Uprobe7 => Some(u8::from(*self) - u8::from(Self::Uprobe0)),
+            Self::Kprobe0 | Self::Kprobe1 | Self::Kprobe2 | Self::Kprobe3 |
+            Self::Kprobe4 | Self::Kprobe5 | Self::Kprobe6 | Self::Kprobe7 => Some(u8::from(*self) - u8::from(Self::Kprobe0)),
+            Self::Tracepoint0 | Self::Tracepoint1 | Self::Tracepoint2 | Self::Tracepoint3 |
+            Self::Tracepoint4 | Self::Tracepoint5 | Self::Tracepoint6 | Self::Tracepoint7 => Some(u8::from(*self) - u8::from(Self::Tracepoint0)),
+            Self::Usdt0 | Self::Usdt1 | Self::Usdt2 | Self::Usdt3 |
+            Self::Usdt4 | Self::Usdt5 | Self::Usdt6 | Self::Usdt7 => Some(u8::from(*self) - u8::from(Self::Usdt0)),
+            Self::Raw0 | Self::Raw1 | Self::Raw2 | Self::Raw3 |
+            Self::Raw4 | Self::Raw5 | Self::Raw6 | Self::Raw7 => Some(u8::from(*self) - u8::from(Self::Raw0)),
             _ => None,
         }
     }
 
+    pub fn uprobe(slot: u8) -> Option<PerfEventType> {
+        if slot >= UPROBE_SLOTS { return None }
+        PerfEventType::try_from(u8::from(PerfEventType::Uprobe0) + slot).ok()
+    }
+
+    pub fn kprobe(slot: u8) -> Option<PerfEventType> {
+        if slot >= KPROBE_SLOTS { return None }
+        PerfEventType::try_from(u8::from(PerfEventType::Kprobe0) + slot).ok()
+    }
+
+    pub fn tracepoint(slot: u8) -> Option<PerfEventType> {
+        if slot >= TRACEPOINT_SLOTS { return None }
+        PerfEventType::try_from(u8::from(PerfEventType::Tracepoint0) + slot).ok()
+    }
+
+    pub fn usdt(slot: u8) -> Option<PerfEventType> {
+        if slot >= USDT_SLOTS { return None }
+        PerfEventType::try_from(u8::from(PerfEventType::Usdt0) + slot).ok()
+    }
+
+    pub fn raw(slot: u8) -> Option<PerfEventType> {
+        if slot >= RAW_SLOTS { return None }
+        PerfEventType::try_from(u8::from(PerfEventType::Raw0) + slot).ok()
+    }
+
+    // (cache, op, result) indices for hw cache events, see HW_CACHE_NAMES and friends
+    pub fn hw_cache(&self) -> Option<(u8, u8, u8)> {
+        let id = u8::from(*self);
+        if id < u8::from(Self::L1dReadAccess) || id > u8::from(Self::NodePrefetchMiss) { return None }
+        let index = id - u8::from(Self::L1dReadAccess);
+        Some((index / 6, index % 6 / 2, index % 2))
+    }
+
+    // perf calls reads loads and writes stores, so both work
+    pub fn hw_cache_from_str(thing: &str) -> Option<PerfEventType> {
+        let mut parts = thing.split("_");
+        let cache = HW_CACHE_NAMES.iter().position(|name| Some(*name) == parts.next())?;
+        let op = match parts.next()? {
+            "read" | "load" => 0,
+            "write" | "store" => 1,
+            "prefetch" => 2,
+            _ => return None,
+        };
+        let result = match parts.next()? {
+            "access" | "accesses" => 0,
+            "miss" | "misses" => 1,
+            _ => return None,
+        };
+        if parts.next().is_some() { return None }
+
+        PerfEventType::try_from(u8::from(Self::L1dReadAccess) + (cache * 6 + op * 2 + result) as u8).ok()
+    }
+
+    // the name users pass on the command line, which is just the ebpf program name without the event_ bit
+    pub fn name(&self) -> String {
+        match self {
+            Self::None => String::from("none"),
+            Self::Any => String::from("all"),
+            Self::Syscall => String::from("syscalls"),
+            Self::BlockIo => String::from("block_io"),
+            Self::Alloc => String::from("alloc_bytes"),
+            Self::Free => String::from("frees"),
+            Self::LiveBytes => String::from("live_bytes"),
+            Self::LockWait => String::from("lock_wait"),
+            Self::TcpSend => String::from("tcp_send"),
+            Self::TcpRecv => String::from("tcp_recv"),
+            Self::TcpRetransmit => String::from("tcp_retransmit"),
+            _ => self.ebpf_from_self().map(|name| name.strip_prefix("event_").unwrap_or(&name).to_string()).unwrap_or(String::from("unknown")),
+        }
+    }
+
+    // events whose samples get broken down by PerfSample.key on top of by pid
+    pub fn keyed(&self) -> bool {
+        match self {
+            Self::Syscall | Self::BlockIo | Self::LockWait => true,
+            Self::TcpSend | Self::TcpRecv | Self::TcpRetransmit => true,
+            _ => false,
+        }
+    }
+
+    // events where the tree should add up PerfSample.value instead of counting samples
+    pub fn weighted(&self) -> bool {
+        match self {
+            Self::Alloc | Self::LiveBytes | Self::LockWait | Self::TcpSend | Self::TcpRecv => true,
+            _ => false,
+        }
+    }
+
+    // events that measure something each time (a latency, a size or a probe argument) instead of just
+    // happening, their values get a histogram even when they come out as 0
+    pub fn valued(&self) -> bool {
+        match self {
+            Self::Syscall | Self::BlockIo | Self::LockWait | Self::Alloc | Self::TcpSend | Self::TcpRecv => true,
+            Self::Uprobe0 | Self::Uprobe1 | Self::Uprobe2 | Self::Uprobe3 |
+            Self::Uprobe4 | Self::Uprobe5 | Self::Uprobe6 | Self::Uprobe7 => true,
+            Self::Kprobe0 | Self::Kprobe1 | Self::Kprobe2 | Self::Kprobe3 |
+            Self::Kprobe4 | Self::Kprobe5 | Self::Kprobe6 | Self::Kprobe7 => true,
+            Self::Usdt0 | Self::Usdt1 | Self::Usdt2 | Self::Usdt3 |
+            Self::Usdt4 | Self::Usdt5 | Self::Usdt6 | Self::Usdt7 => true,
+            _ => false,
+        }
+    }
+
+    // what the tree adds up for this event
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Self::Alloc | Self::LiveBytes | Self::TcpSend | Self::TcpRecv => "bytes",
+            Self::LockWait => "nanoseconds",
+            _ => "count",
+        }
+    }
+
+    // what PerfSample.value (and so the histograms) is in, which isnt always what the tree adds up.
+    // probes time the call, except usdt probes which carry whatever argument the program passed
+    pub fn value_unit(&self) -> &'static str {
+        match self {
+            Self::Alloc | Self::LiveBytes | Self::TcpSend | Self::TcpRecv => "bytes",
+            Self::Usdt0 | Self::Usdt1 | Self::Usdt2 | Self::Usdt3 |
+            Self::Usdt4 | Self::Usdt5 | Self::Usdt6 | Self::Usdt7 => "count",
+            _ => "nanoseconds",
+        }
+    }
+
+    // what the event gets sampled at when -e doesnt say. cycles, instructions and clocks tick way too fast
+    // for one period to mean the same thing on every machine so those go by frequency, the rest get a
+    // period that fits how often they tend to happen
+    pub fn default_sampling(&self) -> Sampling {
+        match self {
+            Self::CpuCycles | Self::RefCpuCycles | Self::BusCycles | Self::Instructions => Sampling::Frequency(99),
+            Self::StalledCyclesFront | Self::StalledCyclesBack => Sampling::Frequency(99),
+            Self::CpuClock | Self::TaskClock => Sampling::Frequency(99),
+            Self::CacheMiss | Self::CacheReferences | Self::BranchMisses | Self::BranchInstructions => Sampling::Period(10000),
+            Self::PageFaults | Self::PageFaultsMin => Sampling::Period(100),
+            Self::Raw0 | Self::Raw1 | Self::Raw2 | Self::Raw3 |
+            Self::Raw4 | Self::Raw5 | Self::Raw6 | Self::Raw7 => Sampling::Frequency(99),
+            _ if self.hw_cache().is_some() => Sampling::Period(10000),
+            // context switches, migrations and the other rare ones are worth seeing every time
+            _ => Sampling::Period(1),
+        }
+    }
+
     pub fn ebpf_from_str(thing: &String) -> Option<String> {
         return PerfEventType::ebpf_from_self(&PerfEventType::from_str(thing).ok()?);
     }
@@ -188,6 +751,16 @@ impl PerfEventType {
             Self::AlignmentFaults => Ok(PerfEventConfig::Software(SoftwareEvent::AlignmentFaults)),
             Self::EmulationFaults => Ok(PerfEventConfig::Software(SoftwareEvent::EmulationFaults)),
             Self::CgroupSwitches => Ok(PerfEventConfig::Software(SoftwareEvent::CgroupSwitches)),
+
+            // hw cache stuff
+            _ if self.hw_cache().is_some() => {
+                let (cache, op, result) = self.hw_cache().unwrap();
+                Ok(PerfEventConfig::HwCache {
+                    event: [HwCacheEvent::L1d, HwCacheEvent::L1i, HwCacheEvent::Ll, HwCacheEvent::Dtlb, HwCacheEvent::Itlb, HwCacheEvent::Bpu, HwCacheEvent::Node][cache as usize],
+                    operation: [HwCacheOp::Read, HwCacheOp::Write, HwCacheOp::Prefetch][op as usize],
+                    result: [HwCacheResult::Access, HwCacheResult::Miss][result as usize],
+                })
+            }
             _ => Err(FlextraceError::NoPerfSwId(self.ebpf_from_self().unwrap_or(String::from("no perf event config compatible version of this PerfEventType?")))),
         }
     }
@@ -2,16 +2,20 @@
 #![no_main]
 
 use core::panic;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
 use aya_ebpf::bindings::BPF_F_USER_STACK;
 use aya_ebpf::cty::c_void;
-use aya_ebpf::helpers::generated::bpf_get_stackid;
+use aya_ebpf::helpers::{bpf_probe_read_kernel, bpf_probe_read_user};
+use aya_ebpf::bindings::{BPF_F_NO_PREALLOC, BPF_NOEXIST};
+use aya_ebpf::helpers::generated::{bpf_get_attach_cookie, bpf_get_current_ancestor_cgroup_id, bpf_get_current_cgroup_id, bpf_get_current_task, bpf_get_smp_processor_id, bpf_get_stackid, bpf_ktime_get_ns};
 use aya_ebpf::{EbpfContext, bpf_printk};
-use aya_ebpf::macros::{map, perf_event};
-use aya_ebpf::programs::PerfEventContext;
-use aya_ebpf::maps::{HashMap, RingBuf, StackTrace};
+use aya_ebpf::macros::{kprobe, kretprobe, map, perf_event, tracepoint, uprobe, uretprobe};
+use aya_ebpf::programs::{PerfEventContext, ProbeContext, RetProbeContext, TracePointContext};
+use aya_ebpf::maps::{Array, HashMap, LpmTrie, LruHashMap, RingBuf, StackTrace};
+use aya_ebpf::maps::lpm_trie::Key;
 use aya_log_ebpf::info;
-use flextrace_common::{PerfSample, PerfEventType};
+use flextrace_common::{AF_INET, AF_INET6, ALLOC_SIZE_BUCKETS, ALLOW_CGROUPS, AllocKey, AllocStats, ALLOW_COMMS, ALLOW_TGIDS, ALLOW_UIDS, EventMask, NsPidOffsets, PerfSample, PerfEventType, SockOffsets, SocketTuple, UPROBE_SLOTS, USDT_ARG_CONST, USDT_ARG_DEREF, USDT_ARG_REG, USDT_ARG_SPECS, UsdtArgSpec};
 
 #[map(name = "PERF_EVENTS")]
 pub static PERF_EVENTS: RingBuf = RingBuf::with_byte_size(1000 * 3000, 0); // ~3MB, exact amount handled by aya
@@ -19,44 +23,681 @@ pub static PERF_EVENTS: RingBuf = RingBuf::with_byte_size(1000 * 3000, 0); // ~3
 #[map(name = "PERF_STACK_TRACES")]
 pub static PERF_STACK_TRACES: StackTrace = StackTrace::with_max_entries(5000, 0); //~5MB i think? maybe in the future make this a runtime toggleable thing
 
+// when each uprobed/kprobed function was entered, keyed by probe_key()
+#[map(name = "PROBE_STARTS")]
+pub static PROBE_STARTS: HashMap<u64, ProbeStart> = HashMap::with_max_entries(10240, 0);
+
+#[derive(Copy, Clone)]
+pub struct ProbeStart {
+    timestamp: u64,
+    stack_id: Option<i64>,
+    kernel_stack_id: Option<i64>,
+    key: u64,
+}
+
+// same thing for syscalls, keyed by thread id since a thread can only be in one syscall at a time. lru
+// since syscalls like exit never come back out and would otherwise fill it up
+#[map(name = "SYSCALL_STARTS")]
+pub static SYSCALL_STARTS: LruHashMap<u32, ProbeStart> = LruHashMap::with_max_entries(10240, 0);
+
+// how to fetch the argument at each attached usdt probe site, indexed by the attach cookie
+#[map(name = "USDT_ARGS")]
+pub static USDT_ARGS: Array<UsdtArgSpec> = Array::with_max_entries(USDT_ARG_SPECS, 0);
+
+// filled in by userspace from the kernels BTF, see ns_pids
+#[map(name = "NS_PID_OFFSETS")]
+pub static NS_PID_OFFSETS: Array<NsPidOffsets> = Array::with_max_entries(1, 0);
+
 #[map(name = "PERF_CONFIG")]
 //10k processes ought to be enough for anybody
-pub static PERF_CONFIG: HashMap<u32, (u32, bool)> = HashMap::with_max_entries(10000, 0);
+pub static PERF_CONFIG: HashMap<u32, (EventMask, bool)> = HashMap::with_max_entries(10000, 0);
 
-fn handle_perf_event(ctx: PerfEventContext, e_type: u8) -> u32 {
-    let mut stackid: Option<i64> = None;
+// which of the allowlists below are turned on, see ALLOW_TGIDS and friends. with none of them on everything gets through
+#[map(name = "ALLOW_FILTER")]
+pub static ALLOW_FILTER: Array<u8> = Array::with_max_entries(1, 0);
 
-    if let Some(config) = unsafe { PERF_CONFIG.get(&ctx.pid()) } {
-        //check if we should filter this event out based on the event type and pid
-        if config.0 & (1 << e_type) != 0 {
-            return 0;
-        }
+#[map(name = "ALLOWED_TGIDS")]
+pub static ALLOWED_TGIDS: HashMap<u32, u8> = HashMap::with_max_entries(1024, 0);
 
-        //do a stack trace if the frame pointer stack trace flag is true
-        if config.1 {
-            stackid = Some(unsafe {bpf_get_stackid(ctx.as_ptr() as *mut c_void, &PERF_STACK_TRACES as *const _ as *mut c_void, BPF_F_USER_STACK as u64) });
-        }
+#[map(name = "ALLOWED_UIDS")]
+pub static ALLOWED_UIDS: HashMap<u32, u8> = HashMap::with_max_entries(1024, 0);
+
+#[map(name = "ALLOWED_CGROUPS")]
+pub static ALLOWED_CGROUPS: HashMap<u64, u8> = HashMap::with_max_entries(1024, 0);
+
+// comm prefixes, the trie matches the longest one thats a prefix of the full comm we look up with
+#[map(name = "ALLOWED_COMMS")]
+pub static ALLOWED_COMMS: LpmTrie<[u8; 16], u8> = LpmTrie::with_max_entries(256, BPF_F_NO_PREALLOC);
+
+// a task has to be on every allowlist thats turned on to get through
+fn allowed<C: EbpfContext>(ctx: &C) -> bool {
+    allowed_task(ctx.tgid(), ctx.uid(), || ctx.command().unwrap_or([0u8; 16]), cgroup_allowed)
+}
+
+// same as allowed but for a sample we took earlier, when it gets sent from some other tasks context.
+// we cant walk the ancestors of a cgroup thats not the running tasks, so that list got checked when
+// the sample was first taken
+fn sample_allowed(sample: &PerfSample) -> bool {
+    allowed_task(sample.tgid, sample.uid, || sample.cmd, || true)
+}
+
+fn allowed_task(tgid: u32, uid: u32, comm: impl Fn() -> [u8; 16], cgroup_allowed: impl Fn() -> bool) -> bool {
+    let filter = ALLOW_FILTER.get(0).copied().unwrap_or(0);
+    if filter == 0 { return true }
+
+    if filter & ALLOW_TGIDS != 0 && unsafe { ALLOWED_TGIDS.get(&tgid) }.is_none() { return false }
+    if filter & ALLOW_UIDS != 0 && unsafe { ALLOWED_UIDS.get(&uid) }.is_none() { return false }
+    if filter & ALLOW_CGROUPS != 0 && !cgroup_allowed() { return false }
+    if filter & ALLOW_COMMS != 0 && ALLOWED_COMMS.get(&Key::new(128, comm())).is_none() { return false }
+    true
+}
+
+// a cgroup on the list lets everything under it through too, so go down from the root to the tasks own cgroup
+fn cgroup_allowed() -> bool {
+    for level in 0..16 {
+        let id = unsafe { bpf_get_current_ancestor_cgroup_id(level) };
+        if id == 0 { return false }
+        if unsafe { ALLOWED_CGROUPS.get(&id) }.is_some() { return true }
     }
+    false
+}
 
-    let sample = PerfSample {
+// None means this event is filtered out for whatever ctx is running, otherwise whether we should grab a stack trace
+fn check_config<C: EbpfContext>(ctx: &C, e_type: u8) -> Option<bool> {
+    if !allowed(ctx) { return None }
+    check_pid_config(ctx.pid(), e_type)
+}
+
+// just the PERF_CONFIG part, for samples that get sent from some other tasks context
+fn check_pid_config(pid: u32, e_type: u8) -> Option<bool> {
+    match unsafe { PERF_CONFIG.get(&pid) } {
+        //check if we should filter this event out based on the event type and pid
+        Some(config) if config.0.contains(e_type) => None,
+        Some(config) => Some(config.1),
+        None => Some(false),
+    }
+}
+
+fn user_stack_id<C: EbpfContext>(ctx: &C) -> i64 {
+    unsafe { bpf_get_stackid(ctx.as_ptr() as *mut c_void, &PERF_STACK_TRACES as *const _ as *mut c_void, BPF_F_USER_STACK as u64) }
+}
+
+fn kernel_stack_id<C: EbpfContext>(ctx: &C) -> i64 {
+    unsafe { bpf_get_stackid(ctx.as_ptr() as *mut c_void, &PERF_STACK_TRACES as *const _ as *mut c_void, 0) }
+}
+
+fn submit_sample<C: EbpfContext>(ctx: &C, e_type: u8, stack_id: Option<i64>, kernel_stack_id: Option<i64>, value: u64, key: u64, failed: bool) {
+    submit(ctx, new_sample(ctx, e_type, stack_id, kernel_stack_id, value, key, failed));
+}
+
+// a sample of whatever task ctx is running in right now
+fn new_sample<C: EbpfContext>(ctx: &C, e_type: u8, stack_id: Option<i64>, kernel_stack_id: Option<i64>, value: u64, key: u64, failed: bool) -> PerfSample {
+    let (ns_pid, ns_tgid) = ns_pids();
+    PerfSample {
         event_type: PerfEventType::try_from(e_type).unwrap(),
         pid: ctx.pid(),
         tgid: ctx.tgid(),
         uid: ctx.uid(),
         gid: ctx.gid(),
         cmd: ctx.command().unwrap_or([0u8; 16]),
-        stack_id: stackid,
+        stack_id: stack_id,
+        kernel_stack_id: kernel_stack_id,
+        timestamp: unsafe { bpf_ktime_get_ns() },
+        cpu: unsafe { bpf_get_smp_processor_id() },
+        value: value,
+        key: key,
+        failed: failed,
+        cgroup: unsafe { bpf_get_current_cgroup_id() },
+        ns_pid,
+        ns_tgid,
+        socket: SocketTuple::default(),
+    }
+}
+
+// pid and tgid of the running task as its own pid namespace sees them, which is the last of the ids
+// its struct pid has (one per namespace level). (0, 0) when userspace couldnt find the offsets
+fn ns_pids() -> (u32, u32) {
+    let Some(offsets) = NS_PID_OFFSETS.get(0) else { return (0, 0) };
+    if offsets.task_thread_pid == 0 { return (0, 0) }
+
+    let ns_id = |task: u64| -> Option<u32> {
+        let pid = read_kernel::<u64>(task, offsets.task_thread_pid as usize)?;
+        if pid == 0 { return None }
+        // MAX_PID_NS_LEVEL is 32
+        let level = read_kernel::<u32>(pid, offsets.pid_level as usize)?.min(31) as usize;
+        read_kernel::<i32>(pid, offsets.pid_numbers as usize + level * offsets.upid_size as usize + offsets.upid_nr as usize).map(|nr| nr as u32)
     };
 
+    let task = unsafe { bpf_get_current_task() };
+    let leader = read_kernel::<u64>(task, offsets.task_group_leader as usize).unwrap_or(0);
+    match (ns_id(task), ns_id(leader)) {
+        (Some(pid), Some(tgid)) => (pid, tgid),
+        _ => (0, 0),
+    }
+}
+
+fn submit<C: EbpfContext>(ctx: &C, sample: PerfSample) {
     if let Some(mut buf) = PERF_EVENTS.reserve::<PerfSample>(0) {
         buf.write(sample);
         buf.submit(0);
     }
     else {
         unsafe { bpf_printk!(b"could not reserve space in PERF_EVENTS buffer"); }
-        info!(&ctx, "could not reserve space in PERF_EVENTS buffer!");
+        info!(ctx, "could not reserve space in PERF_EVENTS buffer!");
     }
+}
 
+// perf events and tracepoints, both are just a hit with no value
+fn handle_perf_event<C: EbpfContext>(ctx: C, e_type: u8) -> u32 {
+    let Some(want_stack) = check_config(&ctx, e_type) else { return 0 };
+
+    //do a stack trace if the frame pointer stack trace flag is true
+    let stackid = if want_stack { Some(user_stack_id(&ctx)) } else { None };
+
+    submit_sample(&ctx, e_type, stackid, None, 0, 0, false);
+    0
+}
+
+// the thread id goes in the top bits and the event in the bottom ones, pid_max is 2^22 so this never collides
+fn probe_key(pid: u32, e_type: u8) -> u64 {
+    ((pid as u64) << 8) | e_type as u64
+}
+
+// the stacks are grabbed on entry since by the time the function returns its frame is already gone.
+// recursive calls of the same function on the same thread overwrite each other, so only the innermost one counts
+fn handle_probe_entry<C: EbpfContext>(ctx: &C, e_type: u8, kernel_stack: bool) -> u32 {
+    let Some(want_stack) = check_config(ctx, e_type) else { return 0 };
+
+    let start = ProbeStart {
+        timestamp: unsafe { bpf_ktime_get_ns() },
+        stack_id: if want_stack { Some(user_stack_id(ctx)) } else { None },
+        kernel_stack_id: if want_stack && kernel_stack { Some(kernel_stack_id(ctx)) } else { None },
+        key: 0,
+    };
+    let _ = PROBE_STARTS.insert(&probe_key(ctx.pid(), e_type), &start, 0);
+
+    0
+}
+
+fn handle_probe_return<C: EbpfContext>(ctx: &C, e_type: u8) -> u32 {
+    let key = probe_key(ctx.pid(), e_type);
+    let Some(start) = (unsafe { PROBE_STARTS.get(&key) }).copied() else { return 0 };
+    let _ = PROBE_STARTS.remove(&key);
+
+    let latency = unsafe { bpf_ktime_get_ns() } - start.timestamp;
+    submit_sample(ctx, e_type, start.stack_id, start.kernel_stack_id, latency, 0, false);
+
+    0
+}
+
+// same thing libbpf does, the registers are read out of pt_regs with a probe read since the
+// verifier wont let us index into the context with a runtime offset
+fn usdt_arg<C: EbpfContext>(ctx: &C) -> u64 {
+    let index = unsafe { bpf_get_attach_cookie(ctx.as_ptr()) } as u32;
+    let Some(spec) = USDT_ARGS.get(index) else { return 0 };
+
+    let raw: u64 = match spec.kind {
+        USDT_ARG_CONST => spec.value as u64,
+        USDT_ARG_REG | USDT_ARG_DEREF => {
+            let reg_ptr = unsafe { (ctx.as_ptr() as *const u8).add(spec.reg_offset as usize) } as *const u64;
+            let Ok(reg) = (unsafe { bpf_probe_read_kernel(reg_ptr) }) else { return 0 };
+
+            if spec.kind == USDT_ARG_REG { reg }
+            else {
+                match unsafe { bpf_probe_read_user((reg as i64 + spec.value) as *const u64) } {
+                    Ok(value) => value,
+                    Err(_) => return 0,
+                }
+            }
+        }
+        _ => return 0,
+    };
+
+    // cut it down to the args size and sign extend if it is signed
+    let bits = (spec.size.unsigned_abs() as u32 * 8).min(64);
+    if bits == 64 || bits == 0 { return raw }
+    let shift = 64 - bits;
+    if spec.size < 0 { ((raw << shift) as i64 >> shift) as u64 } else { (raw << shift) >> shift }
+}
+
+fn handle_usdt(ctx: ProbeContext, e_type: u8) -> u32 {
+    let Some(want_stack) = check_config(&ctx, e_type) else { return 0 };
+
+    let stackid = if want_stack { Some(user_stack_id(&ctx)) } else { None };
+
+    submit_sample(&ctx, e_type, stackid, None, usdt_arg(&ctx), 0, false);
+    0
+}
+
+// raw_syscalls:sys_enter is `long id; unsigned long args[6]` after the 8 byte common tracepoint header
+// and sys_exit is `long id; long ret`, see /sys/kernel/tracing/events/raw_syscalls/*/format
+const SYSCALL_ID_OFFSET: usize = 8;
+const SYSCALL_RET_OFFSET: usize = 16;
+
+#[tracepoint]
+pub fn sys_enter(ctx: TracePointContext) -> u32 {
+    let e_type = PerfEventType::Syscall as u8;
+    let Some(want_stack) = check_config(&ctx, e_type) else { return 0 };
+    let Ok(id) = (unsafe { ctx.read_at::<i64>(SYSCALL_ID_OFFSET) }) else { return 0 };
+
+    let start = ProbeStart {
+        timestamp: unsafe { bpf_ktime_get_ns() },
+        stack_id: if want_stack { Some(user_stack_id(&ctx)) } else { None },
+        kernel_stack_id: None,
+        key: id as u64,
+    };
+    let _ = SYSCALL_STARTS.insert(&ctx.pid(), &start, 0);
+
+    0
+}
+
+#[tracepoint]
+pub fn sys_exit(ctx: TracePointContext) -> u32 {
+    let Some(start) = (unsafe { SYSCALL_STARTS.get(&ctx.pid()) }).copied() else { return 0 };
+    let _ = SYSCALL_STARTS.remove(&ctx.pid());
+
+    let ret = unsafe { ctx.read_at::<i64>(SYSCALL_RET_OFFSET) }.unwrap_or(0);
+    let latency = unsafe { bpf_ktime_get_ns() } - start.timestamp;
+
+    // only -4095..-1 are errors, anything below that is a real return value like an mmap address
+    submit_sample(&ctx, PerfEventType::Syscall as u8, start.stack_id, None, latency, start.key, ret < 0 && ret >= -4095);
+    0
+}
+
+// block:block_bio_queue, block:block_rq_issue and block:block_rq_complete all start with
+// `dev_t dev; sector_t sector;` after the common header, complete has `int error` where issue has the byte count
+const BLOCK_DEV_OFFSET: usize = 8;
+const BLOCK_SECTOR_OFFSET: usize = 16;
+const BLOCK_ERROR_OFFSET: usize = 28;
+
+// bios by sector with whoever submitted them, block_bio_queue runs in the submitting task. by the time
+// the request gets issued the current task is often a kworker or whoever flushed the plug, so issue picks
+// the submitter up from here. its dev is the partitions while requests have the whole disks, but the
+// sector is already remapped to the disk so thats all the key there is. bios that got merged into a
+// request never get looked up and age out
+#[map(name = "BLOCK_QUEUED")]
+pub static BLOCK_QUEUED: LruHashMap<u64, PerfSample> = LruHashMap::with_max_entries(10240, 0);
+
+// in flight requests by [dev, sector], the sample is filled in with whoever submitted the request since
+// completions happen in interrupt context where the current task has nothing to do with it
+#[map(name = "BLOCK_STARTS")]
+pub static BLOCK_STARTS: LruHashMap<[u64; 2], PerfSample> = LruHashMap::with_max_entries(10240, 0);
+
+fn block_request(ctx: &TracePointContext) -> Option<[u64; 2]> {
+    let dev = unsafe { ctx.read_at::<u32>(BLOCK_DEV_OFFSET) }.ok()?;
+    let sector = unsafe { ctx.read_at::<u64>(BLOCK_SECTOR_OFFSET) }.ok()?;
+    Some([dev as u64, sector])
+}
+
+#[tracepoint]
+pub fn block_bio_queue(ctx: TracePointContext) -> u32 {
+    let e_type = PerfEventType::BlockIo as u8;
+    let Some(want_stack) = check_config(&ctx, e_type) else { return 0 };
+    let Ok(sector) = (unsafe { ctx.read_at::<u64>(BLOCK_SECTOR_OFFSET) }) else { return 0 };
+
+    let stackid = if want_stack { Some(user_stack_id(&ctx)) } else { None };
+    let _ = BLOCK_QUEUED.insert(&sector, &new_sample(&ctx, e_type, stackid, None, 0, 0, false), 0);
+
+    0
+}
+
+#[tracepoint]
+pub fn block_rq_issue(ctx: TracePointContext) -> u32 {
+    let Some(request) = block_request(&ctx) else { return 0 };
+
+    // a request starts at its first bio. ones we didnt see queued (from before we attached) are
+    // left out rather than charged to whatever happens to be running
+    let Some(mut sample) = (unsafe { BLOCK_QUEUED.get(&request[1]) }).copied() else { return 0 };
+    let _ = BLOCK_QUEUED.remove(&request[1]);
+
+    sample.timestamp = unsafe { bpf_ktime_get_ns() };
+    sample.key = request[0];
+    let _ = BLOCK_STARTS.insert(&request, &sample, 0);
+
+    0
+}
+
+#[tracepoint]
+pub fn block_rq_complete(ctx: TracePointContext) -> u32 {
+    let Some(request) = block_request(&ctx) else { return 0 };
+    let Some(mut sample) = (unsafe { BLOCK_STARTS.get(&request) }).copied() else { return 0 };
+    let _ = BLOCK_STARTS.remove(&request);
+
+    let now = unsafe { bpf_ktime_get_ns() };
+    sample.value = now - sample.timestamp;
+    sample.timestamp = now;
+    sample.failed = unsafe { ctx.read_at::<i32>(BLOCK_ERROR_OFFSET) }.unwrap_or(0) != 0;
+    submit(&ctx, sample);
+
+    0
+}
+
+// an allocation call that hasnt returned yet, keyed by probe_key(tid, ALLOC_*) so an mmap from inside
+// malloc doesnt clobber the malloc its part of. old is the block realloc was handed
+#[derive(Copy, Clone)]
+pub struct PendingAlloc {
+    size: u64,
+    old: u64,
+}
+
+#[map(name = "ALLOC_PENDING")]
+pub static ALLOC_PENDING: LruHashMap<u64, PendingAlloc> = LruHashMap::with_max_entries(10240, 0);
+
+// blocks that havent been freed yet by [tgid, address], with their size and the totals they count towards
+#[derive(Copy, Clone)]
+pub struct LiveAlloc {
+    size: u64,
+    key: AllocKey,
+}
+
+// not an lru, a block that got evicted would never have its free subtracted. once its full new blocks
+// just arent counted at all, ALLOC_SKIPPED has how many
+#[map(name = "LIVE_ALLOCS")]
+pub static LIVE_ALLOCS: HashMap<[u64; 2], LiveAlloc> = HashMap::with_max_entries(262144, 0);
+
+#[map(name = "ALLOC_SKIPPED")]
+pub static ALLOC_SKIPPED: Array<u64> = Array::with_max_entries(1, 0);
+
+// read and never cleared by userspace, once its full allocations from stacks that arent in it yet go uncounted
+#[map(name = "ALLOC_STATS")]
+pub static ALLOC_STATS: HashMap<AllocKey, AllocStats> = HashMap::with_max_entries(16384, 0);
+
+const ALLOC_HEAP: u8 = 0;
+const ALLOC_MMAP: u8 = 1;
+
+fn alloc_entry(ctx: &ProbeContext, kind: u8, size: u64, old: u64) -> u32 {
+    if check_config(ctx, PerfEventType::Alloc as u8).is_none() { return 0 }
+    let _ = ALLOC_PENDING.insert(&probe_key(ctx.pid(), kind), &PendingAlloc { size, old }, 0);
+    0
+}
+
+// the stack is grabbed here instead of on entry since the caller is still on it either way
+fn alloc_return(ctx: &RetProbeContext, kind: u8) -> u32 {
+    let key = probe_key(ctx.pid(), kind);
+    let Some(pending) = (unsafe { ALLOC_PENDING.get(&key) }).copied() else { return 0 };
+    let _ = ALLOC_PENDING.remove(&key);
+
+    // NULL from the malloc family and MAP_FAILED from mmap. a realloc that failed left the old block
+    // where it was, so that only counts as freed once we know it worked. realloc(ptr, 0) is the
+    // exception, that frees ptr and hands back NULL
+    let address: u64 = ctx.ret().unwrap_or(0);
+    if address == 0 && pending.size == 0 { free_address(ctx, pending.old) }
+    if address == 0 || address == u64::MAX { return 0 }
+    free_address(ctx, pending.old);
+
+    // the address got handed out again without us seeing the free
+    free_address(ctx, address);
+
+    // tracked before its counted, so every block in the totals can have its free taken off again
+    let want_stack = check_config(ctx, PerfEventType::Alloc as u8).unwrap_or(false);
+    let alloc_key = AllocKey { tgid: ctx.tgid(), pid: ctx.pid(), stack_id: if want_stack { user_stack_id(ctx) } else { -1 } };
+    let live_key = [ctx.tgid() as u64, address];
+    if LIVE_ALLOCS.insert(&live_key, &LiveAlloc { size: pending.size, key: alloc_key }, 0).is_err() {
+        if let Some(skipped) = ALLOC_SKIPPED.get_ptr_mut(0) { unsafe { atomic_add(skipped, 1) } }
+        return 0
+    }
+    if !count_alloc(ctx, &alloc_key, pending.size) { let _ = LIVE_ALLOCS.remove(&live_key); }
+    0
+}
+
+// false if there was no room left for a new stack
+fn count_alloc<C: EbpfContext>(ctx: &C, key: &AllocKey, size: u64) -> bool {
+    if unsafe { ALLOC_STATS.get(key) }.is_none() {
+        let (ns_pid, ns_tgid) = ns_pids();
+        let stats = AllocStats {
+            uid: ctx.uid(),
+            gid: ctx.gid(),
+            cgroup: unsafe { bpf_get_current_cgroup_id() },
+            ns_pid,
+            ns_tgid,
+            cmd: ctx.command().unwrap_or([0u8; 16]),
+            ..Default::default()
+        };
+        // some other cpu might have beaten us to it, which is fine
+        let _ = ALLOC_STATS.insert(key, &stats, BPF_NOEXIST as u64);
+    }
+    let Some(stats) = ALLOC_STATS.get_ptr_mut(key) else { return false };
+
+    let bucket = ((u64::BITS - size.leading_zeros()) as usize).min(ALLOC_SIZE_BUCKETS - 1);
+    unsafe {
+        atomic_add(&raw mut (*stats).allocs, 1);
+        atomic_add(&raw mut (*stats).bytes, size);
+        AtomicU32::from_ptr(&raw mut (*stats).sizes[bucket]).fetch_add(1, Ordering::Relaxed);
+        // racy, but only ever off by whichever big allocation lost
+        if size > (*stats).max { (*stats).max = size }
+    }
+    true
+}
+
+// frees can come from any thread of the process, they go against whoever allocated the block
+fn free_address<C: EbpfContext>(ctx: &C, address: u64) {
+    if address == 0 { return }
+    let live_key = [ctx.tgid() as u64, address];
+    let Some(live) = (unsafe { LIVE_ALLOCS.get(&live_key) }).copied() else { return };
+    let _ = LIVE_ALLOCS.remove(&live_key);
+
+    let Some(stats) = ALLOC_STATS.get_ptr_mut(&live.key) else { return };
+    unsafe {
+        atomic_add(&raw mut (*stats).frees, 1);
+        atomic_add(&raw mut (*stats).freed_bytes, live.size);
+    }
+}
+
+// several cpus can be in the same stacks totals at once
+unsafe fn atomic_add(counter: *mut u64, value: u64) {
+    unsafe { AtomicU64::from_ptr(counter) }.fetch_add(value, Ordering::Relaxed);
+}
+
+#[uprobe]
+pub fn alloc_malloc(ctx: ProbeContext) -> u32 {
+    return alloc_entry(&ctx, ALLOC_HEAP, ctx.arg::<u64>(0).unwrap_or(0), 0);
+}
+
+#[uprobe]
+pub fn alloc_calloc(ctx: ProbeContext) -> u32 {
+    let count = ctx.arg::<u64>(0).unwrap_or(0);
+    let size = ctx.arg::<u64>(1).unwrap_or(0);
+    return alloc_entry(&ctx, ALLOC_HEAP, count.saturating_mul(size), 0);
+}
+
+// realloc is a free of the old pointer and a malloc of the new size, the free happens on return
+#[uprobe]
+pub fn alloc_realloc(ctx: ProbeContext) -> u32 {
+    return alloc_entry(&ctx, ALLOC_HEAP, ctx.arg::<u64>(1).unwrap_or(0), ctx.arg::<u64>(0).unwrap_or(0));
+}
+
+#[uretprobe]
+pub fn alloc_heap_return(ctx: RetProbeContext) -> u32 {
+    return alloc_return(&ctx, ALLOC_HEAP);
+}
+
+const MAP_ANONYMOUS: u64 = 0x20;
+
+// the allocator mmaps big chunks itself, those are already counted by the malloc they came from. file
+// mappings arent memory anyone allocated, only anonymous ones count
+#[uprobe]
+pub fn alloc_mmap(ctx: ProbeContext) -> u32 {
+    if ctx.arg::<u64>(3).unwrap_or(0) & MAP_ANONYMOUS == 0 { return 0 }
+    if unsafe { ALLOC_PENDING.get(&probe_key(ctx.pid(), ALLOC_HEAP)) }.is_some() { return 0 }
+    return alloc_entry(&ctx, ALLOC_MMAP, ctx.arg::<u64>(1).unwrap_or(0), 0);
+}
+
+#[uretprobe]
+pub fn alloc_mmap_return(ctx: RetProbeContext) -> u32 {
+    return alloc_return(&ctx, ALLOC_MMAP);
+}
+
+// free and munmap both take the address first
+#[uprobe]
+pub fn alloc_free(ctx: ProbeContext) -> u32 {
+    free_address(&ctx, ctx.arg::<u64>(0).unwrap_or(0));
+    0
+}
+
+// syscalls:sys_enter_futex has `u32 *uaddr; int futex_op;` after the common header and syscall nr,
+// sys_exit_futex is `long ret` in the same spot as raw_syscalls
+const FUTEX_UADDR_OFFSET: usize = 16;
+const FUTEX_OP_OFFSET: usize = 24;
+
+// the ops that actually block, with FUTEX_PRIVATE_FLAG and FUTEX_CLOCK_REALTIME masked off
+const FUTEX_CMD_MASK: i32 = !(128 | 256);
+const FUTEX_WAIT: i32 = 0;
+const FUTEX_LOCK_PI: i32 = 6;
+const FUTEX_WAIT_BITSET: i32 = 9;
+const FUTEX_WAIT_REQUEUE_PI: i32 = 11;
+const FUTEX_LOCK_PI2: i32 = 13;
+
+// keyed by thread id like SYSCALL_STARTS, the key is the futex address
+#[map(name = "LOCK_STARTS")]
+pub static LOCK_STARTS: LruHashMap<u32, ProbeStart> = LruHashMap::with_max_entries(10240, 0);
+
+#[tracepoint]
+pub fn sys_enter_futex(ctx: TracePointContext) -> u32 {
+    let e_type = PerfEventType::LockWait as u8;
+    let Some(want_stack) = check_config(&ctx, e_type) else { return 0 };
+
+    let op = unsafe { ctx.read_at::<i32>(FUTEX_OP_OFFSET) }.unwrap_or(-1) & FUTEX_CMD_MASK;
+    if !matches!(op, FUTEX_WAIT | FUTEX_LOCK_PI | FUTEX_WAIT_BITSET | FUTEX_WAIT_REQUEUE_PI | FUTEX_LOCK_PI2) { return 0 }
+    let Ok(uaddr) = (unsafe { ctx.read_at::<u64>(FUTEX_UADDR_OFFSET) }) else { return 0 };
+
+    let start = ProbeStart {
+        timestamp: unsafe { bpf_ktime_get_ns() },
+        stack_id: if want_stack { Some(user_stack_id(&ctx)) } else { None },
+        kernel_stack_id: None,
+        key: uaddr,
+    };
+    let _ = LOCK_STARTS.insert(&ctx.pid(), &start, 0);
+
+    0
+}
+
+#[tracepoint]
+pub fn sys_exit_futex(ctx: TracePointContext) -> u32 {
+    let Some(start) = (unsafe { LOCK_STARTS.get(&ctx.pid()) }).copied() else { return 0 };
+    let _ = LOCK_STARTS.remove(&ctx.pid());
+
+    // timeouts and EAGAIN (the value changed before we got to sleep) come back as errors
+    let ret = unsafe { ctx.read_at::<i64>(SYSCALL_RET_OFFSET) }.unwrap_or(0);
+    let waited = unsafe { bpf_ktime_get_ns() } - start.timestamp;
+
+    submit_sample(&ctx, PerfEventType::LockWait as u8, start.stack_id, None, waited, start.key, ret < 0);
+    0
+}
+
+// filled in by userspace from the kernels BTF, see socket_tuple
+#[map(name = "SOCK_OFFSETS")]
+pub static SOCK_OFFSETS: Array<SockOffsets> = Array::with_max_entries(1, 0);
+
+// the last sample sent on each socket, retransmits happen from timers and softirqs so this is how they
+// get pinned on the process that owns the connection. entries go away in tcp_close so a recycled sk
+// doesnt inherit them
+#[map(name = "TCP_OWNERS")]
+pub static TCP_OWNERS: LruHashMap<u64, PerfSample> = LruHashMap::with_max_entries(10240, 0);
+
+// the socket each thread is in tcp_sendmsg on, by thread id
+#[map(name = "TCP_SENDS")]
+pub static TCP_SENDS: LruHashMap<u32, u64> = LruHashMap::with_max_entries(10240, 0);
+
+fn read_kernel<T>(base: u64, offset: usize) -> Option<T> {
+    unsafe { bpf_probe_read_kernel((base as usize + offset) as *const T) }.ok()
+}
+
+// None for sockets that arent ipv4 or ipv6, and for everything when userspace couldnt find the offsets
+fn socket_tuple(sk: u64) -> Option<SocketTuple> {
+    let offsets = SOCK_OFFSETS.get(0)?;
+    if offsets.family == 0 { return None }
+
+    let mut socket = SocketTuple::default();
+    socket.family = read_kernel::<u16>(sk, offsets.family as usize)?;
+    socket.sport = read_kernel::<u16>(sk, offsets.num as usize)?;
+    socket.dport = u16::from_be(read_kernel::<u16>(sk, offsets.dport as usize)?);
+
+    match socket.family {
+        AF_INET => {
+            let saddr = read_kernel::<[u8; 4]>(sk, offsets.rcv_saddr as usize)?;
+            let daddr = read_kernel::<[u8; 4]>(sk, offsets.daddr as usize)?;
+            socket.saddr[..4].copy_from_slice(&saddr);
+            socket.daddr[..4].copy_from_slice(&daddr);
+        }
+        AF_INET6 => {
+            if offsets.v6_daddr == 0 { return None }
+            socket.saddr = read_kernel::<[u8; 16]>(sk, offsets.v6_rcv_saddr as usize)?;
+            socket.daddr = read_kernel::<[u8; 16]>(sk, offsets.v6_daddr as usize)?;
+        }
+        _ => return None,
+    }
+
+    Some(socket)
+}
+
+fn handle_tcp<C: EbpfContext>(ctx: &C, e_type: u8, sk: u64, bytes: u64) -> u32 {
+    let Some(want_stack) = check_config(ctx, e_type) else { return 0 };
+    let Some(socket) = socket_tuple(sk) else { return 0 };
+
+    let stackid = if want_stack { Some(user_stack_id(ctx)) } else { None };
+    let mut sample = new_sample(ctx, e_type, stackid, None, bytes, 0, false);
+    sample.socket = socket;
+
+    if e_type == PerfEventType::TcpSend as u8 {
+        let _ = TCP_OWNERS.insert(&sk, &sample, 0);
+    }
+    submit(ctx, sample);
+
+    0
+}
+
+// int tcp_sendmsg(struct sock *sk, struct msghdr *msg, size_t size). size is only what was asked for, a
+// nonblocking socket can take less, so the sample waits for the return value
+#[kprobe]
+pub fn tcp_sendmsg(ctx: ProbeContext) -> u32 {
+    if check_config(&ctx, PerfEventType::TcpSend as u8).is_none() { return 0 }
+    let _ = TCP_SENDS.insert(&ctx.pid(), &ctx.arg::<u64>(0).unwrap_or(0), 0);
+    0
+}
+
+#[kretprobe]
+pub fn tcp_sendmsg_return(ctx: RetProbeContext) -> u32 {
+    let Some(sk) = (unsafe { TCP_SENDS.get(&ctx.pid()) }).copied() else { return 0 };
+    let _ = TCP_SENDS.remove(&ctx.pid());
+
+    let sent: i64 = ctx.ret().unwrap_or(0);
+    if sent <= 0 { return 0 }
+    return handle_tcp(&ctx, PerfEventType::TcpSend as u8, sk, sent as u64);
+}
+
+// void tcp_cleanup_rbuf(struct sock *sk, int copied), called once the data has been copied to userspace
+#[kprobe]
+pub fn tcp_cleanup_rbuf(ctx: ProbeContext) -> u32 {
+    let copied = ctx.arg::<i32>(1).unwrap_or(0);
+    if copied <= 0 { return 0 }
+    return handle_tcp(&ctx, PerfEventType::TcpRecv as u8, ctx.arg::<u64>(0).unwrap_or(0), copied as u64);
+}
+
+// int tcp_retransmit_skb(struct sock *sk, struct sk_buff *skb, int segs)
+#[kprobe]
+pub fn tcp_retransmit_skb(ctx: ProbeContext) -> u32 {
+    let e_type = PerfEventType::TcpRetransmit as u8;
+    let sk = ctx.arg::<u64>(0).unwrap_or(0);
+
+    // whoever is running right now just happened to get interrupted, so without an owner theres nobody
+    // to charge this to. the tuple check catches an sk that got reused without us seeing it close
+    let Some(mut sample) = (unsafe { TCP_OWNERS.get(&sk) }).copied() else { return 0 };
+    if socket_tuple(sk) != Some(sample.socket) { return 0 }
+    if !sample_allowed(&sample) || check_pid_config(sample.pid, e_type).is_none() { return 0 }
+
+    sample.event_type = PerfEventType::TcpRetransmit;
+    sample.timestamp = unsafe { bpf_ktime_get_ns() };
+    sample.cpu = unsafe { bpf_get_smp_processor_id() };
+    sample.value = 0;
+    submit(&ctx, sample);
+
+    0
+}
+
+// void tcp_close(struct sock *sk, long timeout)
+#[kprobe]
+pub fn tcp_close(ctx: ProbeContext) -> u32 {
+    let _ = TCP_OWNERS.remove(&ctx.arg::<u64>(0).unwrap_or(0));
     0
 }
 
@@ -157,6 +798,441 @@ pub fn event_cgroup_switches(ctx: PerfEventContext) -> u32 {
     return handle_perf_event(ctx, PerfEventType::CgroupSwitches.into());
 }
 
+//hardware cache events
+#[perf_event]
+pub fn event_l1d_read_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1dReadAccess.into());
+}
+
+#[perf_event]
+pub fn event_l1d_read_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1dReadMiss.into());
+}
+
+#[perf_event]
+pub fn event_l1d_write_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1dWriteAccess.into());
+}
+
+#[perf_event]
+pub fn event_l1d_write_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1dWriteMiss.into());
+}
+
+#[perf_event]
+pub fn event_l1d_prefetch_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1dPrefetchAccess.into());
+}
+
+#[perf_event]
+pub fn event_l1d_prefetch_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1dPrefetchMiss.into());
+}
+
+#[perf_event]
+pub fn event_l1i_read_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1iReadAccess.into());
+}
+
+#[perf_event]
+pub fn event_l1i_read_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1iReadMiss.into());
+}
+
+#[perf_event]
+pub fn event_l1i_write_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1iWriteAccess.into());
+}
+
+#[perf_event]
+pub fn event_l1i_write_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1iWriteMiss.into());
+}
+
+#[perf_event]
+pub fn event_l1i_prefetch_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1iPrefetchAccess.into());
+}
+
+#[perf_event]
+pub fn event_l1i_prefetch_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::L1iPrefetchMiss.into());
+}
+
+#[perf_event]
+pub fn event_ll_read_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::LlReadAccess.into());
+}
+
+#[perf_event]
+pub fn event_ll_read_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::LlReadMiss.into());
+}
+
+#[perf_event]
+pub fn event_ll_write_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::LlWriteAccess.into());
+}
+
+#[perf_event]
+pub fn event_ll_write_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::LlWriteMiss.into());
+}
+
+#[perf_event]
+pub fn event_ll_prefetch_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::LlPrefetchAccess.into());
+}
+
+#[perf_event]
+pub fn event_ll_prefetch_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::LlPrefetchMiss.into());
+}
+
+#[perf_event]
+pub fn event_dtlb_read_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::DtlbReadAccess.into());
+}
+
+#[perf_event]
+pub fn event_dtlb_read_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::DtlbReadMiss.into());
+}
+
+#[perf_event]
+pub fn event_dtlb_write_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::DtlbWriteAccess.into());
+}
+
+#[perf_event]
+pub fn event_dtlb_write_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::DtlbWriteMiss.into());
+}
+
+#[perf_event]
+pub fn event_dtlb_prefetch_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::DtlbPrefetchAccess.into());
+}
+
+#[perf_event]
+pub fn event_dtlb_prefetch_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::DtlbPrefetchMiss.into());
+}
+
+#[perf_event]
+pub fn event_itlb_read_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::ItlbReadAccess.into());
+}
+
+#[perf_event]
+pub fn event_itlb_read_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::ItlbReadMiss.into());
+}
+
+#[perf_event]
+pub fn event_itlb_write_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::ItlbWriteAccess.into());
+}
+
+#[perf_event]
+pub fn event_itlb_write_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::ItlbWriteMiss.into());
+}
+
+#[perf_event]
+pub fn event_itlb_prefetch_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::ItlbPrefetchAccess.into());
+}
+
+#[perf_event]
+pub fn event_itlb_prefetch_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::ItlbPrefetchMiss.into());
+}
+
+#[perf_event]
+pub fn event_bpu_read_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::BpuReadAccess.into());
+}
+
+#[perf_event]
+pub fn event_bpu_read_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::BpuReadMiss.into());
+}
+
+#[perf_event]
+pub fn event_bpu_write_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::BpuWriteAccess.into());
+}
+
+#[perf_event]
+pub fn event_bpu_write_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::BpuWriteMiss.into());
+}
+
+#[perf_event]
+pub fn event_bpu_prefetch_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::BpuPrefetchAccess.into());
+}
+
+#[perf_event]
+pub fn event_bpu_prefetch_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::BpuPrefetchMiss.into());
+}
+
+#[perf_event]
+pub fn event_node_read_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::NodeReadAccess.into());
+}
+
+#[perf_event]
+pub fn event_node_read_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::NodeReadMiss.into());
+}
+
+#[perf_event]
+pub fn event_node_write_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::NodeWriteAccess.into());
+}
+
+#[perf_event]
+pub fn event_node_write_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::NodeWriteMiss.into());
+}
+
+#[perf_event]
+pub fn event_node_prefetch_access(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::NodePrefetchAccess.into());
+}
+
+#[perf_event]
+pub fn event_node_prefetch_miss(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::NodePrefetchMiss.into());
+}
+
+//uprobes, there is only the one pair of programs and the slot (see UPROBE_SLOTS) they were attached
+//for comes in as the attach cookie
+fn uprobe_event<C: EbpfContext>(ctx: &C) -> Option<u8> {
+    let slot = unsafe { bpf_get_attach_cookie(ctx.as_ptr()) };
+    if slot >= UPROBE_SLOTS as u64 { return None }
+    Some(PerfEventType::Uprobe0 as u8 + slot as u8)
+}
+
+#[uprobe]
+pub fn uprobe_entry(ctx: ProbeContext) -> u32 {
+    let Some(e_type) = uprobe_event(&ctx) else { return 0 };
+    return handle_probe_entry(&ctx, e_type, false);
+}
+
+#[uretprobe]
+pub fn uprobe_return(ctx: RetProbeContext) -> u32 {
+    let Some(e_type) = uprobe_event(&ctx) else { return 0 };
+    return handle_probe_return(&ctx, e_type);
+}
+
+//kprobes, see KPROBE_SLOTS
+#[kprobe]
+pub fn kprobe_0(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 0, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_0(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 0);
+}
+
+#[kprobe]
+pub fn kprobe_1(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 1, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_1(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 1);
+}
+
+#[kprobe]
+pub fn kprobe_2(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 2, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_2(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 2);
+}
+
+#[kprobe]
+pub fn kprobe_3(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 3, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_3(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 3);
+}
+
+#[kprobe]
+pub fn kprobe_4(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 4, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_4(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 4);
+}
+
+#[kprobe]
+pub fn kprobe_5(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 5, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_5(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 5);
+}
+
+#[kprobe]
+pub fn kprobe_6(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 6, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_6(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 6);
+}
+
+#[kprobe]
+pub fn kprobe_7(ctx: ProbeContext) -> u32 {
+    return handle_probe_entry(&ctx, PerfEventType::Kprobe0 as u8 + 7, true);
+}
+
+#[kretprobe]
+pub fn kretprobe_7(ctx: RetProbeContext) -> u32 {
+    return handle_probe_return(&ctx, PerfEventType::Kprobe0 as u8 + 7);
+}
+
+//tracepoints, see TRACEPOINT_SLOTS
+#[tracepoint]
+pub fn tracepoint_0(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 0);
+}
+
+#[tracepoint]
+pub fn tracepoint_1(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 1);
+}
+
+#[tracepoint]
+pub fn tracepoint_2(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 2);
+}
+
+#[tracepoint]
+pub fn tracepoint_3(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 3);
+}
+
+#[tracepoint]
+pub fn tracepoint_4(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 4);
+}
+
+#[tracepoint]
+pub fn tracepoint_5(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 5);
+}
+
+#[tracepoint]
+pub fn tracepoint_6(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 6);
+}
+
+#[tracepoint]
+pub fn tracepoint_7(ctx: TracePointContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Tracepoint0 as u8 + 7);
+}
+
+//usdt probes, see USDT_SLOTS
+#[uprobe]
+pub fn usdt_0(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 0);
+}
+
+#[uprobe]
+pub fn usdt_1(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 1);
+}
+
+#[uprobe]
+pub fn usdt_2(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 2);
+}
+
+#[uprobe]
+pub fn usdt_3(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 3);
+}
+
+#[uprobe]
+pub fn usdt_4(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 4);
+}
+
+#[uprobe]
+pub fn usdt_5(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 5);
+}
+
+#[uprobe]
+pub fn usdt_6(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 6);
+}
+
+#[uprobe]
+pub fn usdt_7(ctx: ProbeContext) -> u32 {
+    return handle_usdt(ctx, PerfEventType::Usdt0 as u8 + 7);
+}
+
+//raw pmu events, see RAW_SLOTS
+#[perf_event]
+pub fn raw_0(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 0);
+}
+
+#[perf_event]
+pub fn raw_1(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 1);
+}
+
+#[perf_event]
+pub fn raw_2(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 2);
+}
+
+#[perf_event]
+pub fn raw_3(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 3);
+}
+
+#[perf_event]
+pub fn raw_4(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 4);
+}
+
+#[perf_event]
+pub fn raw_5(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 5);
+}
+
+#[perf_event]
+pub fn raw_6(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 6);
+}
+
+#[perf_event]
+pub fn raw_7(ctx: PerfEventContext) -> u32 {
+    return handle_perf_event(ctx, PerfEventType::Raw0 as u8 + 7);
+}
+
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo) -> ! {
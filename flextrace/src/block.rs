@@ -0,0 +1,12 @@
+use std::fs;
+
+// the kernel keeps dev_t as 12 bits of major and 20 bits of minor, which is what the block tracepoints hand us
+pub fn device_name(dev: u64) -> String {
+    let (major, minor) = (dev >> 20, dev & 0xfffff);
+
+    let uevent = fs::read_to_string(format!("/sys/dev/block/{major}:{minor}/uevent")).unwrap_or_default();
+    match uevent.lines().find_map(|line| line.strip_prefix("DEVNAME=")) {
+        Some(name) => name.to_string(),
+        None => format!("{major}:{minor}"),
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+
+const BTF_MAGIC: u16 = 0xeb9f;
+const BTF_KIND_STRUCT: u32 = 4;
+const BTF_KIND_UNION: u32 = 5;
+
+// just enough of the kernels BTF to find where struct members are on the running kernel. aya cant do
+// CO-RE relocations from the ebpf side, so whatever layout ebpf needs gets looked up here and handed over
+pub struct Btf {
+    types: Vec<BtfType>, // by id - 1, id 0 is void
+    strings: Vec<u8>,
+}
+
+struct BtfType {
+    kind: u32,
+    name: u32,
+    size: u32,
+    members: Vec<(u32, u32, u32)>, // name, type and offset in bits, only for structs and unions
+}
+
+impl Btf {
+    pub fn from_sys() -> Option<Btf> {
+        Btf::parse(&fs::read("/sys/kernel/btf/vmlinux").ok()?)
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Btf> {
+        let u32_at = |at: usize| Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?));
+        if u16::from_le_bytes(data.get(0..2)?.try_into().ok()?) != BTF_MAGIC { return None }
+
+        // header is magic, version, flags, hdr_len and then type_off, type_len, str_off, str_len after it
+        let header_len = u32_at(4)? as usize;
+        let types_start = header_len + u32_at(8)? as usize;
+        let types_end = types_start + u32_at(12)? as usize;
+        let strings_start = header_len + u32_at(16)? as usize;
+        let strings = data.get(strings_start..strings_start + u32_at(20)? as usize)?.to_vec();
+
+        let mut types = Vec::new();
+        let mut at = types_start;
+        while at < types_end {
+            let (name, info, size) = (u32_at(at)?, u32_at(at + 4)?, u32_at(at + 8)?);
+            let (kind, vlen, kind_flag) = ((info >> 24) & 0x1f, (info & 0xffff) as usize, info >> 31 == 1);
+            at += 12;
+
+            let mut members = Vec::new();
+            if kind == BTF_KIND_STRUCT || kind == BTF_KIND_UNION {
+                for member in 0..vlen {
+                    let member_at = at + member * 12;
+                    let offset = u32_at(member_at + 8)?;
+                    // with kind_flag the top byte is the bitfield size
+                    members.push((u32_at(member_at)?, u32_at(member_at + 4)?, if kind_flag { offset & 0xffffff } else { offset }));
+                }
+            }
+
+            // whatever comes after the common part, depends on the kind
+            at += match kind {
+                1 | 14 | 17 => 4, // int, var, decl_tag
+                3 => 12, // array
+                4 | 5 | 15 | 19 => vlen * 12, // struct, union, datasec, enum64
+                6 | 13 => vlen * 8, // enum, func_proto
+                0 | 2 | 7..=12 | 16 | 18 => 0,
+                _ => return None,
+            };
+            types.push(BtfType { kind, name, size, members });
+        }
+
+        Some(Btf { types, strings })
+    }
+
+    fn name(&self, offset: u32) -> &[u8] {
+        let rest = self.strings.get(offset as usize..).unwrap_or(&[]);
+        &rest[..rest.iter().position(|byte| *byte == 0).unwrap_or(rest.len())]
+    }
+
+    // the first struct called name that isnt just a declaration
+    fn find_struct(&self, name: &str) -> Option<&BtfType> {
+        self.types.iter().find(|ty| ty.kind == BTF_KIND_STRUCT && ty.size != 0 && self.name(ty.name) == name.as_bytes())
+    }
+
+    pub fn struct_size(&self, name: &str) -> Option<u32> {
+        Some(self.find_struct(name)?.size)
+    }
+
+    // byte offset of member in struct name, looking through anonymous structs and unions too
+    pub fn member_offset(&self, name: &str, member: &str) -> Option<u32> {
+        self.find_member(self.find_struct(name)?, member.as_bytes()).map(|bits| bits / 8)
+    }
+
+    fn find_member(&self, ty: &BtfType, member: &[u8]) -> Option<u32> {
+        for (name, member_type, offset) in &ty.members {
+            if self.name(*name) == member { return Some(*offset) }
+            if *name != 0 { continue }
+            let Some(inner) = self.types.get((*member_type as usize).wrapping_sub(1)) else { continue };
+            if let Some(inner_offset) = self.find_member(inner, member) {
+                return Some(offset + inner_offset)
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // builds a BTF blob out of (kind, name, size, members) with the names put in the string section
+    fn blob(types: &[(u32, &str, u32, &[(&str, u32, u32)])]) -> Vec<u8> {
+        let mut strings = vec![0u8];
+        let mut string = |name: &str| {
+            if name.is_empty() { return 0 }
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+            (strings.len() - name.len() - 1) as u32
+        };
+
+        let mut type_data = Vec::new();
+        for (kind, name, size, members) in types {
+            for word in [string(name), kind << 24 | members.len() as u32, *size] {
+                type_data.extend_from_slice(&word.to_le_bytes());
+            }
+            for (member_name, member_type, offset) in *members {
+                for word in [string(member_name), *member_type, *offset] {
+                    type_data.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&BTF_MAGIC.to_le_bytes());
+        data.extend_from_slice(&[1, 0]);
+        for word in [24u32, 0, type_data.len() as u32, type_data.len() as u32, strings.len() as u32] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&type_data);
+        data.extend_from_slice(&strings);
+        data
+    }
+
+    #[test]
+    fn finds_member_offsets() {
+        let data = blob(&[
+            (8, "pid_t", 0, &[]), // a typedef so there is nothing after it
+            (BTF_KIND_UNION, "", 8, &[("group_leader", 1, 0), ("other", 1, 0)]),
+            (BTF_KIND_STRUCT, "task_struct", 0, &[]),
+            (BTF_KIND_STRUCT, "task_struct", 64, &[("state", 1, 0), ("thread_pid", 1, 128), ("", 2, 256)]),
+        ]);
+        let btf = Btf::parse(&data).unwrap();
+
+        assert_eq!(btf.struct_size("task_struct"), Some(64));
+        assert_eq!(btf.member_offset("task_struct", "thread_pid"), Some(16));
+        assert_eq!(btf.member_offset("task_struct", "group_leader"), Some(32));
+        assert_eq!(btf.member_offset("task_struct", "missing"), None);
+        assert_eq!(btf.member_offset("pid", "level"), None);
+        assert!(Btf::parse(&data[4..]).is_none());
+    }
+}
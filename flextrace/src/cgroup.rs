@@ -0,0 +1,49 @@
+use std::{fs, path::{Path, PathBuf}};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// the cgroup v2 path of pid, out of the `0::/path` line in /proc/pid/cgroup
+pub fn cgroup_path(pid: u32) -> Option<String> {
+    let cgroups = fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+    cgroups.lines().find_map(|line| line.strip_prefix("0::")).map(String::from)
+}
+
+// container runtimes put the 64 hex digit container id somewhere in the cgroup path, like
+// /system.slice/docker-<id>.scope, /kubepods.slice/.../cri-containerd-<id>.scope or /docker/<id>
+pub fn container_id(path: &str) -> Option<String> {
+    path.split(|c: char| !c.is_ascii_hexdigit()).find(|part| part.len() == 64).map(String::from)
+}
+
+// what to call the cgroup pid is in, the short container id if its a container and the path otherwise
+pub fn cgroup_name(pid: u32) -> Option<String> {
+    let path = cgroup_path(pid)?;
+    match container_id(&path) {
+        Some(id) => Some(format!("container {}", &id[..12])),
+        None => Some(path),
+    }
+}
+
+// every cgroup under the cgroup root that belongs to the container id, which can be shortened like docker does
+pub fn find_container(id: &str) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    find_container_in(Path::new(CGROUP_ROOT), &id.to_lowercase(), 0, &mut found);
+    found
+}
+
+// the cgroups of a container dont go very deep, kubernetes is the worst at about 5 levels
+fn find_container_in(dir: &Path, id: &str, depth: usize, found: &mut Vec<PathBuf>) {
+    if depth > 8 { return }
+    let Ok(entries) = fs::read_dir(dir) else { return };
+
+    for entry in entries.flatten() {
+        if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) { continue }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        if container_id(&name).is_some_and(|container| container.starts_with(id)) {
+            // whatever is under it is the same container
+            found.push(entry.path());
+            continue;
+        }
+        find_container_in(&entry.path(), id, depth + 1, found);
+    }
+}
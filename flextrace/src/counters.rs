@@ -0,0 +1,57 @@
+use std::{fs::File, io::{self, Read}, mem::size_of, os::fd::BorrowedFd};
+
+use flextrace_common::Sampling;
+
+use crate::perf_event::{self, PerfEventAttr};
+
+pub const PERF_TYPE_HARDWARE: u32 = 0;
+pub const PERF_TYPE_SOFTWARE: u32 = 1;
+pub const PERF_TYPE_HW_CACHE: u32 = 3;
+pub const PERF_TYPE_RAW: u32 = 4;
+
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_ATTR_FLAG_INHERIT: u64 = 1 << 1;
+const PERF_ATTR_FLAG_FREQ: u64 = 1 << 10;
+
+// a sampling perf event that runs a bpf program on every sample and can still be read for how many
+// events it counted, so the samples can be scaled up without a second event taking another pmu counter
+pub struct Counter {
+    file: File,
+    _link: File,
+}
+
+impl Counter {
+    // pid None samples everything on cpu, otherwise that thread on any cpu. threads it starts later get
+    // sampled through inherit too, but the kernel only adds their counts into this one once they exit
+    pub fn open(event_type: u32, config: u64, sampling: Sampling, pid: Option<u32>, cpu: Option<u32>, program: BorrowedFd) -> io::Result<Self> {
+        let (sample_period, freq) = match sampling {
+            Sampling::Period(period) => (period, 0),
+            Sampling::Frequency(frequency) => (frequency, PERF_ATTR_FLAG_FREQ),
+        };
+        let attr = PerfEventAttr {
+            event_type,
+            size: size_of::<PerfEventAttr>() as u32,
+            config,
+            sample_period,
+            read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+            flags: freq | if pid.is_some() { PERF_ATTR_FLAG_INHERIT } else { 0 },
+            ..Default::default()
+        };
+
+        let pid = pid.map(|pid| pid as libc::c_int).unwrap_or(-1);
+        let cpu = cpu.map(|cpu| cpu as libc::c_int).unwrap_or(-1);
+        let file = perf_event::perf_event_open(&attr, pid, cpu)?;
+        let link = perf_event::attach_program(program, &file, 0)?;
+        Ok(Counter { file, _link: link })
+    }
+
+    // (value, time enabled, time running), running is less than enabled when there were more events
+    // than hardware counters and the kernel had to take turns
+    pub fn read(&self) -> io::Result<(u64, u64, u64)> {
+        let mut buf = [0u8; 24];
+        (&self.file).read_exact(&mut buf)?;
+        let field = |index: usize| u64::from_ne_bytes(buf[index * 8..index * 8 + 8].try_into().unwrap());
+        Ok((field(0), field(1), field(2)))
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, fs::{OpenOptions, create_dir_all, read_dir, remove_file}, io::ErrorKind, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use anyhow::Result;
+use flextrace::{SaveData, save_traces};
+use flextrace_common::{EventCount, PerfEventType};
+use log::{debug, error, info, warn};
+use tokio::{signal::unix::{SignalKind, signal}, sync::mpsc::Receiver};
+
+use crate::{perf::PerfManager, server::{ServeRequest, next_request, respond}};
+
+const PROFILE_PREFIX: &str = "flextrace-";
+const PROFILE_SUFFIX: &str = ".ft";
+
+// any of these being None means we don't care about that limit
+pub struct Retention {
+    pub max_files: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+// the perf events stay attached for the whole lifetime of the daemon, we only swap out the
+// tree we're collecting into every window so nothing gets dropped in between files. this is meant to
+// run for as long as the machine is up, so once we're going a bad sample or a full disk only gets
+// logged instead of taking the whole daemon down
+pub async fn run_daemon(mut perf_manager: PerfManager, dir: String, window: Duration, retention: Retention, mut http_requests: Option<Receiver<ServeRequest>>) -> Result<()> {
+    create_dir_all(&dir)?;
+
+    let mut current = SaveData::new();
+    let mut window_counts = perf_manager.read_counts();
+    let mut interval = tokio::time::interval(window);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    // the first tick completes immediately, we don't want an empty file for that
+    interval.tick().await;
+    info!("flextrace daemon writing a profile to {dir} every {}s", window.as_secs());
+
+    loop {
+        tokio::select! {
+            Some(recv) = perf_manager.event_rx.recv() => {
+                let trace = perf_manager.trace_for_sample(&recv).unwrap_or_else(|e| {
+                    warn!("failed to get the stack of a sample, keeping it without one: {e}");
+                    None
+                });
+                current.record(&recv, trace);
+            },
+            _ = interval.tick() => {
+                let counts = perf_manager.read_counts();
+                perf_manager.record_allocs(&mut current);
+                let mut finished = std::mem::replace(&mut current, SaveData::new());
+                finished.event_labels = perf_manager.event_labels.clone();
+                finished.sampling = perf_manager.sampling.clone();
+                finished.counts = counts_since(&counts, &window_counts);
+                window_counts = counts;
+                write_and_retain(&dir, finished, &retention);
+            },
+            Some(request) = next_request(&mut http_requests) => {
+                current.sampling = perf_manager.sampling.clone();
+                current.counts = counts_since(&perf_manager.read_counts(), &window_counts);
+                perf_manager.record_allocs(&mut current);
+                respond(request, &current);
+            },
+            _ = sigterm.recv() => break,
+            _ = sigint.recv() => break,
+        }
+    }
+
+    // dont throw away the partial window we were in the middle of
+    info!("flextrace daemon shutting down");
+    current.event_labels = perf_manager.event_labels.clone();
+    current.sampling = perf_manager.sampling.clone();
+    current.counts = counts_since(&perf_manager.read_counts(), &window_counts);
+    perf_manager.record_allocs(&mut current);
+    write_and_retain(&dir, current, &retention);
+
+    Ok(())
+}
+
+fn write_and_retain(dir: &str, data: SaveData, retention: &Retention) {
+    if let Err(e) = write_window(dir, data) {
+        error!("failed to write profile window to {dir}: {e}");
+    }
+    if let Err(e) = enforce_retention(dir, retention) {
+        warn!("failed to clean up old profiles in {dir}: {e}");
+    }
+}
+
+// the counters keep running across windows, so each window only gets what they counted since it started
+fn counts_since(counts: &HashMap<(PerfEventType, Option<u32>), EventCount>, start: &HashMap<(PerfEventType, Option<u32>), EventCount>) -> HashMap<(PerfEventType, Option<u32>), EventCount> {
+    counts.iter().map(|(key, count)| (*key, count.since(&start.get(key).copied().unwrap_or_default()))).collect()
+}
+
+// two windows can finish in the same second (the last one on shutdown usually does), so the name
+// gets claimed with create_new and a -N on the end if someone already has it
+fn write_window(dir: &str, mut data: SaveData) -> Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut sequence = 0;
+    let path = loop {
+        let name = if sequence == 0 { format!("{PROFILE_PREFIX}{timestamp}{PROFILE_SUFFIX}") } else { format!("{PROFILE_PREFIX}{timestamp}-{sequence}{PROFILE_SUFFIX}") };
+        let path = Path::new(dir).join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => break path,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => sequence += 1,
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    data.tree.update_sorted_cache();
+    save_traces(path.to_string_lossy().to_string(), data)?;
+    debug!("wrote profile window to {}", path.display());
+
+    Ok(())
+}
+
+fn enforce_retention(dir: &str, retention: &Retention) -> Result<()> {
+    let mut profiles: Vec<(PathBuf, SystemTime, u64)> = Vec::new();
+
+    for entry in read_dir(dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // only ever touch files that we wrote ourselves
+        if !name.starts_with(PROFILE_PREFIX) || !name.ends_with(PROFILE_SUFFIX) { continue }
+
+        // it might have been removed out from under us, which is fine
+        match entry.metadata().and_then(|metadata| Ok((metadata.modified()?, metadata.len()))) {
+            Ok((modified, size)) => profiles.push((entry.path(), modified, size)),
+            Err(e) => warn!("skipping {} for retention: {e}", entry.path().display()),
+        }
+    }
+
+    // newest first, so everything past the limits is the oldest stuff
+    profiles.sort_by_key(|profile| std::cmp::Reverse(profile.1));
+
+    let now = SystemTime::now();
+    let mut total_bytes: u64 = 0;
+
+    for (index, (path, modified, size)) in profiles.iter().enumerate() {
+        total_bytes += size;
+
+        let too_many = retention.max_files.is_some_and(|max| index >= max);
+        let too_big = retention.max_bytes.is_some_and(|max| total_bytes > max);
+        let too_old = retention.max_age.is_some_and(|max| now.duration_since(*modified).unwrap_or_default() > max);
+
+        if too_many || too_big || too_old {
+            if let Err(e) = remove_file(path) {
+                warn!("failed to remove old profile {}: {e}", path.display());
+            }
+            else { debug!("removed old profile {}", path.display()); }
+        }
+    }
+
+    Ok(())
+}
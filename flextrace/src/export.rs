@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use flextrace_common::PerfEventType;
+use serde_json::{Map, Value, json};
+
+use crate::{SaveData, Tree, timeline::TimedSample};
+
+// a sample is assumed to cover the time up to the next sample on the same thread (or cpu), but
+// never longer than this so a thread going idle doesn't look like it ran the whole time
+const CHROME_MAX_SLICE_NS: u64 = 10_000_000;
+
+// chrome wants integer pids, and every real pid (including 0 for the idle task) is taken
+const CHROME_CPU_TRACK_PID: i64 = -1;
+
+impl Tree {
+    // counters in the tree are inclusive (every frame on the way down gets bumped), so the
+    // samples that actually ended in this node are whatever the children don't account for
+    pub fn self_count(&self, index: usize, event: PerfEventType) -> u64 {
+        let node = &self.nodes[index];
+        let children: u64 = node.children.values().map(|&child| self.nodes[child].counter(event)).sum();
+        node.counter(event).saturating_sub(children)
+    }
+
+    // calls back with every stack (as node indices from the outermost frame in) that has samples ending in it
+    pub fn for_each_stack(&self, event: PerfEventType, mut callback: impl FnMut(&[usize], u64)) {
+        let mut path: Vec<usize> = Vec::new();
+        let mut to_visit: Vec<(usize, usize)> = self.nodes[0].children.values().map(|&child| (child, 0)).collect();
+
+        while let Some((index, depth)) = to_visit.pop() {
+            path.truncate(depth);
+            path.push(index);
+
+            let count = self.self_count(index, event);
+            if count > 0 { callback(&path, count); }
+
+            for &child in self.nodes[index].children.values() {
+                to_visit.push((child, depth + 1));
+            }
+        }
+    }
+}
+
+// brendan gregg style folded stacks, one line per stack: outer;inner;innermost count
+pub fn folded(tree: &Tree, event: PerfEventType) -> String {
+    let mut out = String::new();
+
+    tree.for_each_stack(event, |path, count| {
+        let frames: Vec<&str> = path.iter().map(|&index| tree.nodes[index].symbol()).collect();
+        out.push_str(&frames.join(";"));
+        out.push_str(&format!(" {count}\n"));
+    });
+
+    out
+}
+
+pub fn json_tree(tree: &Tree) -> Value {
+    fn node_json(tree: &Tree, index: usize) -> Value {
+        let node = &tree.nodes[index];
+        let counters: Map<String, Value> = node.counters.iter().map(|(event, count)| (event.name(), json!(count))).collect();
+        let weights: Map<String, Value> = node.weights.iter().map(|(event, weight)| (event.name(), json!(weight))).collect();
+        let children: Vec<Value> = node.children.values().map(|&child| node_json(tree, child)).collect();
+
+        json!({
+            "name": node.symbol(),
+            "module": node.module(),
+            "address": node.address(),
+            "hits": node.hits,
+            "counters": counters,
+            "weights": weights,
+            "children": children,
+        })
+    }
+
+    node_json(tree, 0)
+}
+
+pub fn json_profile_data(save_data: &SaveData) -> Value {
+    let all_estimates = save_data.estimates();
+    let processes: Map<String, Value> = save_data.data.iter().map(|(pid, profile)| {
+        let events: Map<String, Value> = profile.events.iter().map(|(event, count)| (event.name(), json!(count))).collect();
+        // only the events that had a counter running alongside them
+        let estimates: Map<String, Value> = profile.events.keys().filter_map(|event| {
+            let estimate = all_estimates.get(&(*pid, *event))?;
+            Some((event.name(), json!({ "total": estimate.value, "per_second": estimate.per_second() })))
+        }).collect();
+
+        (pid.to_string(), json!({
+            "name": profile.name.trim_end_matches('\0'),
+            "uid": profile.uid,
+            "gid": profile.gid,
+            "ns_pid": profile.ns_pid,
+            "cgroup": save_data.cgroups.get(&profile.cgroup),
+            "events": events,
+            "estimates": estimates,
+        }))
+    }).collect();
+
+    Value::Object(processes)
+}
+
+// prometheus/openmetrics text exposition, one series per pid and event. the estimate comes from the
+// totals read off the perf events when there are some, otherwise it assumes every sample stands for a full
+// sampling period worth of events (which is how perf scales things), so events sampled by frequency need a total
+pub fn openmetrics(save_data: &SaveData) -> String {
+    let data = &save_data.data;
+    let mut samples = String::new();
+    let mut estimates = String::new();
+    let all_estimates = save_data.estimates();
+
+    let mut pids: Vec<&u32> = data.keys().collect();
+    pids.sort();
+
+    for pid in pids {
+        let profile = &data[pid];
+        let mut events: Vec<(&PerfEventType, &u32)> = profile.events.iter().collect();
+        events.sort_by_key(|(event, _)| u8::from(**event));
+
+        for (event, count) in events {
+            let labels = format!(
+                "pid=\"{pid}\",comm=\"{}\",uid=\"{}\",gid=\"{}\",event=\"{}\"",
+                escape_label(profile.name.trim_end_matches('\0')), profile.uid, profile.gid, event.name(),
+            );
+
+            samples.push_str(&format!("flextrace_samples_total{{{labels}}} {count}\n"));
+            let estimate = match all_estimates.get(&(*pid, *event)) {
+                Some(estimate) => Some(estimate.value),
+                None => save_data.sampling.get(event).and_then(|sampling| sampling.period()).map(|period| *count as u64 * period),
+            };
+            if let Some(estimate) = estimate {
+                estimates.push_str(&format!("flextrace_events_estimated_total{{{labels}}} {estimate}\n"));
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("# TYPE flextrace_samples counter\n");
+    out.push_str("# HELP flextrace_samples perf event samples taken per process\n");
+    out.push_str(&samples);
+    out.push_str("# TYPE flextrace_events_estimated counter\n");
+    out.push_str("# HELP flextrace_events_estimated events counted by the pmu, or samples multiplied by the sampling period\n");
+    out.push_str(&estimates);
+    out.push_str("# EOF\n");
+
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n")
+}
+
+// chrome trace event format (the json one), which chrome://tracing and ui.perfetto.dev both open. every
+// thread gets its samples turned into nested begin/end slices so you get a flame chart over time, and
+// there's an extra "cpus" process with one track per cpu showing which thread was running on it
+pub fn chrome_trace(save: &SaveData, event: PerfEventType) -> Value {
+    let micros = |timestamp: u64| timestamp.saturating_sub(save.timeline.start_ns) as f64 / 1000.0;
+    let name_of = |pid: u32| match save.data.get(&pid) {
+        Some(profile) => profile.name.trim_end_matches('\0').to_string(),
+        None => pid.to_string(),
+    };
+
+    let mut threads: HashMap<(u32, u32), Vec<&TimedSample>> = HashMap::new();
+    let mut cpus: HashMap<u32, Vec<&TimedSample>> = HashMap::new();
+
+    for sample in &save.samples {
+        if event != PerfEventType::None && sample.event != event { continue }
+        threads.entry((sample.tgid, sample.pid)).or_insert_with(Vec::new).push(sample);
+        cpus.entry(sample.cpu).or_insert_with(Vec::new).push(sample);
+    }
+
+    let mut trace_events: Vec<Value> = Vec::new();
+
+    for ((tgid, pid), samples) in threads.iter_mut() {
+        samples.sort_by_key(|sample| sample.timestamp);
+
+        trace_events.push(json!({ "ph": "M", "name": "process_name", "pid": tgid, "tid": pid, "args": { "name": name_of(*tgid) } }));
+        // threads that never got an entry of their own go by their process
+        let thread_name = match save.data.get(pid) {
+            Some(profile) => profile.name.trim_end_matches('\0').to_string(),
+            None => format!("{} {pid}", name_of(*tgid)),
+        };
+        trace_events.push(json!({ "ph": "M", "name": "thread_name", "pid": tgid, "tid": pid, "args": { "name": thread_name } }));
+
+        // frames that currently have a begin event without an end, outermost first
+        let mut open: Vec<String> = Vec::new();
+        let mut covered_until: u64 = 0;
+
+        for (index, sample) in samples.iter().enumerate() {
+            let frames: Vec<String> = if sample.leaf == 0 { vec![format!("[{}]", sample.event.name())] }
+            else {
+                let mut path: Vec<usize> = Vec::new();
+                let mut current_index = sample.leaf;
+                while current_index != 0 {
+                    path.push(current_index);
+                    current_index = save.tree.nodes[current_index].parent;
+                }
+                path.iter().rev().map(|&node| save.tree.nodes[node].symbol().to_string()).collect()
+            };
+
+            // the thread went quiet since the last sample, so everything that was open ended back then
+            let mut still_open = open.len();
+            if sample.timestamp > covered_until { still_open = 0; }
+
+            let common = open[..still_open].iter().zip(&frames).take_while(|(open_frame, frame)| open_frame == frame).count();
+            let end_time = if still_open == 0 { covered_until } else { sample.timestamp };
+            while open.len() > common {
+                trace_events.push(json!({ "ph": "E", "name": open.pop(), "pid": tgid, "tid": pid, "ts": micros(end_time) }));
+            }
+            for frame in &frames[common..] {
+                trace_events.push(json!({ "ph": "B", "name": frame, "pid": tgid, "tid": pid, "ts": micros(sample.timestamp) }));
+                open.push(frame.clone());
+            }
+
+            let limit = sample.timestamp + CHROME_MAX_SLICE_NS;
+            covered_until = samples.get(index + 1).map(|next| next.timestamp.min(limit)).unwrap_or(limit);
+        }
+
+        while let Some(frame) = open.pop() {
+            trace_events.push(json!({ "ph": "E", "name": frame, "pid": tgid, "tid": pid, "ts": micros(covered_until) }));
+        }
+    }
+
+    trace_events.push(json!({ "ph": "M", "name": "process_name", "pid": CHROME_CPU_TRACK_PID, "args": { "name": "cpus" } }));
+
+    for (cpu, samples) in cpus.iter_mut() {
+        samples.sort_by_key(|sample| sample.timestamp);
+        trace_events.push(json!({ "ph": "M", "name": "thread_name", "pid": CHROME_CPU_TRACK_PID, "tid": cpu, "args": { "name": format!("cpu {cpu}") } }));
+
+        // back to back samples from the same thread get glued into one slice
+        let mut current: Option<(u32, u64, u64)> = None; // pid, start, end
+        let flush = |current: Option<(u32, u64, u64)>, trace_events: &mut Vec<Value>| {
+            if let Some((pid, start, end)) = current {
+                trace_events.push(json!({
+                    "ph": "X", "name": format!("{} ({pid})", name_of(pid)), "pid": CHROME_CPU_TRACK_PID, "tid": cpu,
+                    "ts": micros(start), "dur": (end - start) as f64 / 1000.0,
+                }));
+            }
+        };
+
+        for sample in samples.iter() {
+            let end = sample.timestamp + CHROME_MAX_SLICE_NS;
+            match current {
+                Some((pid, start, current_end)) if pid == sample.pid && sample.timestamp <= current_end => current = Some((pid, start, end)),
+                Some((pid, start, current_end)) => {
+                    flush(Some((pid, start, current_end.min(sample.timestamp))), &mut trace_events);
+                    current = Some((sample.pid, sample.timestamp, end));
+                }
+                None => current = Some((sample.pid, sample.timestamp, end)),
+            }
+        }
+        flush(current, &mut trace_events);
+    }
+
+    json!({ "traceEvents": trace_events, "displayTimeUnit": "ns" })
+}
+
+// speedscope's own file format, with a sampled profile for every event over all processes and then
+// one for every event in every process. the per process ones need the timed samples to work out
+// which stacks came from where, the tree on its own doesn't know that
+pub fn speedscope(save: &SaveData) -> Value {
+    let mut frames: Vec<Value> = Vec::new();
+    let mut frame_ids: HashMap<(String, String), usize> = HashMap::new();
+    let mut stack_of = |leaf: usize| -> Vec<usize> {
+        let mut path: Vec<usize> = Vec::new();
+        let mut current_index = leaf;
+        while current_index != 0 {
+            let node = &save.tree.nodes[current_index];
+            let key = (node.symbol().to_string(), node.module().to_string());
+            let next_id = frame_ids.len();
+            path.push(*frame_ids.entry(key).or_insert_with(|| {
+                frames.push(json!({ "name": node.symbol(), "file": node.module() }));
+                next_id
+            }));
+            current_index = node.parent;
+        }
+        path.reverse();
+        path
+    };
+
+    let sampled = |name: String, event: PerfEventType, stacks: Vec<Vec<usize>>, weights: Vec<u64>| -> Value {
+        let total: u64 = weights.iter().sum();
+        // weighted events add up bytes or ns, everything else is just how many samples there were
+        let unit = match event.unit() {
+            "bytes" => "bytes",
+            "nanoseconds" => "nanoseconds",
+            _ => "none",
+        };
+        json!({
+            "type": "sampled", "name": name, "unit": unit,
+            "startValue": 0, "endValue": total,
+            "samples": stacks, "weights": weights,
+        })
+    };
+
+    let mut profiles: Vec<Value> = Vec::new();
+
+    let mut events: Vec<PerfEventType> = save.tree.nodes[0].children.values()
+        .flat_map(|&child| save.tree.nodes[child].counters.keys().chain(save.tree.nodes[child].weights.keys()).copied())
+        .collect();
+    events.sort_by_key(|event| u8::from(*event));
+    events.dedup();
+
+    for &event in &events {
+        let mut stacks: Vec<Vec<usize>> = Vec::new();
+        let mut weights: Vec<u64> = Vec::new();
+        let mut leaves: Vec<(usize, u64)> = Vec::new();
+
+        save.tree.for_each_stack(event, |path, count| leaves.push((*path.last().unwrap(), count)));
+        for (leaf, count) in leaves {
+            stacks.push(stack_of(leaf));
+            weights.push(count);
+        }
+
+        profiles.push(sampled(format!("{} (all processes)", event.name()), event, stacks, weights));
+    }
+
+    let mut per_process: HashMap<(u32, PerfEventType), HashMap<usize, u64>> = HashMap::new();
+    for sample in &save.samples {
+        if sample.leaf == 0 { continue }
+        *per_process.entry((sample.tgid, sample.event)).or_insert_with(HashMap::new).entry(sample.leaf).or_insert(0) += if sample.event.weighted() { sample.value } else { 1 };
+    }
+
+    let mut keys: Vec<(u32, PerfEventType)> = per_process.keys().copied().collect();
+    keys.sort_by_key(|(tgid, event)| (*tgid, u8::from(*event)));
+
+    for key in keys {
+        let (tgid, event) = key;
+        let mut leaves: Vec<(usize, u64)> = per_process[&key].iter().map(|(&leaf, &count)| (leaf, count)).collect();
+        leaves.sort();
+
+        let name = match save.data.get(&tgid) {
+            Some(profile) => format!("{} ({} {tgid})", event.name(), profile.name.trim_end_matches('\0')),
+            None => format!("{} ({tgid})", event.name()),
+        };
+
+        let stacks: Vec<Vec<usize>> = leaves.iter().map(|&(leaf, _)| stack_of(leaf)).collect();
+        let weights: Vec<u64> = leaves.iter().map(|&(_, count)| count).collect();
+        profiles.push(sampled(name, event, stacks, weights));
+    }
+
+    json!({
+        "$schema": "https://www.speedscope.app/file-format-schema.json",
+        "shared": { "frames": frames },
+        "profiles": profiles,
+        "name": "flextrace",
+        "activeProfileIndex": 0,
+        "exporter": "flextrace",
+    })
+}
+
+// the pprof format is just a protobuf message (profile.proto from google/pprof), it's small enough that
+// writing the few fields we need by hand is easier than pulling in a whole protobuf toolchain
+pub fn pprof(tree: &Tree, event: PerfEventType) -> Vec<u8> {
+    let mut strings: Vec<String> = vec![String::new()];
+    let mut string_ids: HashMap<String, i64> = HashMap::new();
+    let mut intern = |thing: &str| -> i64 {
+        if let Some(&id) = string_ids.get(thing) { return id }
+        let id = strings.len() as i64;
+        strings.push(thing.to_string());
+        string_ids.insert(thing.to_string(), id);
+        id
+    };
+
+    let mut profile = ProtoWriter::default();
+
+    // sample_type
+    let mut value_type = ProtoWriter::default();
+    value_type.int(1, intern(&event.name()) as u64);
+    value_type.int(2, intern(event.unit()) as u64);
+    profile.message(1, &value_type);
+
+    // sample, locations are listed innermost frame first and we use node index + 1 as the location id
+    let mut used_nodes: Vec<usize> = Vec::new();
+    tree.for_each_stack(event, |path, count| {
+        let mut sample = ProtoWriter::default();
+        let location_ids: Vec<u64> = path.iter().rev().map(|&index| index as u64 + 1).collect();
+        sample.packed(1, &location_ids);
+        sample.packed(2, &[count]);
+        profile.message(2, &sample);
+        used_nodes.extend_from_slice(path);
+    });
+    used_nodes.sort();
+    used_nodes.dedup();
+
+    // location and function, one function per distinct symbol
+    let mut function_ids: HashMap<String, u64> = HashMap::new();
+    let mut functions = ProtoWriter::default();
+
+    for index in used_nodes {
+        let node = &tree.nodes[index];
+        let next_function_id = function_ids.len() as u64 + 1;
+        let function_id = *function_ids.entry(node.symbol().to_string()).or_insert_with(|| {
+            let mut function = ProtoWriter::default();
+            function.int(1, next_function_id);
+            function.int(2, intern(node.symbol()) as u64);
+            function.int(3, intern(node.symbol()) as u64);
+            function.int(4, intern(node.module()) as u64);
+            functions.message(5, &function);
+            next_function_id
+        });
+
+        let mut line = ProtoWriter::default();
+        line.int(1, function_id);
+
+        let mut location = ProtoWriter::default();
+        location.int(1, index as u64 + 1);
+        location.int(3, node.address());
+        location.message(4, &line);
+        profile.message(4, &location);
+    }
+    profile.bytes.extend_from_slice(&functions.bytes);
+
+    // string_table
+    for thing in &strings {
+        profile.string(6, thing);
+    }
+
+    profile.bytes
+}
+
+#[derive(Default)]
+struct ProtoWriter {
+    bytes: Vec<u8>,
+}
+
+impl ProtoWriter {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn int(&mut self, field: u32, value: u64) {
+        if value == 0 { return }
+        self.key(field, 0);
+        self.varint(value);
+    }
+
+    fn length_delimited(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, field: u32, thing: &str) {
+        self.length_delimited(field, thing.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, message: &ProtoWriter) {
+        self.length_delimited(field, &message.bytes);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u64]) {
+        let mut inner = ProtoWriter::default();
+        for &value in values { inner.varint(value); }
+        self.length_delimited(field, &inner.bytes);
+    }
+}
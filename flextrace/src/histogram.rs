@@ -0,0 +1,77 @@
+use bincode_next::{Decode, Encode};
+
+// log2 histogram, bucket i holds values in [2^(i-1), 2^i) and bucket 0 holds zeroes. the unit is
+// whatever the event puts in PerfSample.value (ns for latencies, bytes for sizes and so on)
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct Histogram {
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (bucket, count) in other.buckets.iter().enumerate() {
+            self.buckets[bucket] += count;
+        }
+
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn mean(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.sum / self.count }
+    }
+
+    // only as good as the buckets, so this is the upper bound of the bucket the percentile falls in
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let target = (self.count as f64 * percentile / 100.0).ceil() as u64;
+        let mut seen: u64 = 0;
+
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target && *count != 0 {
+                if bucket == 0 { return 0 }
+                return ((1u128 << bucket) - 1).min(self.max as u128) as u64;
+            }
+        }
+
+        self.max
+    }
+}
+
+// a histogram plus how many of the recorded things failed, for keyed events like syscalls
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct KeyedStats {
+    pub latency: Histogram,
+    pub errors: u64,
+}
+
+impl KeyedStats {
+    pub fn record(&mut self, value: u64, failed: bool) {
+        self.latency.record(value);
+        if failed { self.errors += 1; }
+    }
+
+    pub fn merge(&mut self, other: &KeyedStats) {
+        self.latency.merge(&other.latency);
+        self.errors += other.errors;
+    }
+}
@@ -1,13 +1,28 @@
 pub use aya::maps::HashMap as AyaHashMap;
 use bincode_next::{Decode, Encode, config, decode_from_slice, encode_to_vec};
-use flextrace_common::PerfEventType;
+use flextrace_common::{AllocStats, EventCount, FlextraceError, PerfEventType, PerfSample, Sampling, SocketTuple};
 use log::trace;
 use ratatui::{buffer::Buffer, layout::{Rect}, style::{Color}, widgets::{Bar, BarChart, Widget}};
 use anyhow::Result;
 
-use std::{cmp::Reverse, collections::HashMap, fs::{read, write}};
+use std::{cmp::Reverse, collections::{HashMap, HashSet}, fs::{read, write}};
 
+pub mod block;
+pub mod cgroup;
+pub mod export;
+pub mod histogram;
+mod btf;
+mod counters;
+mod namespace;
 mod perf;
+mod perf_event;
+pub mod perf_script;
+pub mod syscalls;
+pub mod timeline;
+mod usdt;
+
+use histogram::{Histogram, KeyedStats};
+use timeline::{TimedSample, Timeline};
 
 #[derive(Debug, Encode, Decode)]
 pub struct Tree {
@@ -22,6 +37,7 @@ pub struct Tree {
 #[derive(Debug, Encode, Decode)]
 pub struct Node {
     pub counters: HashMap<PerfEventType, u32>,
+    pub weights: HashMap<PerfEventType, u64>, // summed up values for weighted events, ie bytes for allocations
     pub name: String,
     pub children: HashMap<String, usize>,
     pub hits: u32,
@@ -29,17 +45,40 @@ pub struct Node {
 }
 
 impl Node {
-    pub fn counter(&self, event: PerfEventType) -> u32 {
-        if event == PerfEventType::None { return self.hits }
-        if let Some(hits) = self.counters.get(&event) { return *hits }
+    pub fn counter(&self, event: PerfEventType) -> u64 {
+        if event == PerfEventType::None { return self.hits as u64 }
+        if event.weighted() { return self.weights.get(&event).copied().unwrap_or(0) }
+        if let Some(hits) = self.counters.get(&event) { return *hits as u64 }
         else { 0 }
     }
+
+    // node names look like module:symbol(at)ip, these pull the pieces back out
+    pub fn module(&self) -> &str {
+        match self.name.find(":") {
+            Some(colon_index) => &self.name[..colon_index],
+            None => "",
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        let start = self.name.find(":").map(|colon_index| colon_index + 1).unwrap_or(0);
+        let end = self.name.rfind("(at)").unwrap_or(self.name.len());
+        &self.name[start..end.max(start)]
+    }
+
+    pub fn address(&self) -> u64 {
+        let start = self.name.rfind("(at)").map(|at_index| at_index + 4).or(self.name.find(":").map(|colon_index| colon_index + 1)).unwrap_or(0);
+        self.name[start..].parse().unwrap_or(0)
+    }
 }
 
 #[derive(Debug, Encode, Decode)]
 pub struct ProfileData {
     pub name: String,
+    pub uid: u32,
     pub gid: u32,
+    pub cgroup: u64,
+    pub ns_pid: u32, // the pid inside its own pid namespace, what a container would call it
     pub events: HashMap<PerfEventType, u32>,
 }
 
@@ -47,10 +86,35 @@ pub struct ProfileData {
 pub struct SaveData {
     pub tree: Tree,
     pub data: HashMap<u32, ProfileData>,
+    pub timeline: Timeline,
+    pub samples: Vec<TimedSample>, // only the newest MAX_SAMPLES, for picking time windows and exporting timelines
+    pub histograms: HashMap<u32, HashMap<PerfEventType, Histogram>>, // of PerfSample.value, per pid
+    pub event_labels: HashMap<PerfEventType, String>, // what slotted events like uprobes were attached to
+    pub sampling: HashMap<PerfEventType, Sampling>, // the period or frequency each perf event ran with
+    pub counts: HashMap<(PerfEventType, Option<u32>), EventCount>, // totals read off the perf events by event and thread, None is system wide
+    pub cgroups: HashMap<u64, String>, // the container or cgroup path behind each cgroup id, since pids dont survive a redeploy
+    pub breakdowns: HashMap<PerfEventType, HashMap<u64, HashMap<u32, KeyedStats>>>, // keyed events by event, key, pid
+    pub sockets: HashMap<u64, SocketTuple>, // the connections behind the keys of tcp events
 }
 
 impl Tree {
-    pub fn update(&mut self, trace: Vec<String>, event: PerfEventType) {
+    pub fn new() -> Self {
+        Tree {
+            nodes: vec![Node { counters: HashMap::new(), weights: HashMap::new(), name: "root".to_string(), children: HashMap::new(), hits: 0, parent: 0 }],
+            focused_event: PerfEventType::None,
+            focused_node: 0, selected_node: 0,
+            focused_children_sorted_cache: Vec::new(),
+            display_head_node: 0,
+        }
+    }
+
+    // returns the index of the innermost frame of the trace
+    pub fn update(&mut self, trace: Vec<String>, event: PerfEventType) -> usize {
+        self.update_by(trace, event, 1)
+    }
+
+    // same as update but for count events at once
+    pub fn update_by(&mut self, trace: Vec<String>, event: PerfEventType, count: u32) -> usize {
         trace!("updating tree with new trace with new trace, immediate child is {}", trace[0]);
         let mut current_index = 0;
 
@@ -62,6 +126,7 @@ impl Tree {
                 let new_node = Node {
                     name: name.clone(),
                     counters: HashMap::new(),
+                    weights: HashMap::new(),
                     hits: 0,
                     children: HashMap::new(),
                     parent: current_index,
@@ -73,22 +138,532 @@ impl Tree {
             };
 
             current_index = next_index;
-            self.nodes[current_index].hits += 1;
-            self.nodes[current_index].counters.entry(event).and_modify(|c| *c += 1 ).or_insert(1);
+            self.nodes[current_index].hits += count;
+            *self.nodes[current_index].counters.entry(event).or_insert(0) += count;
+        }
+
+        current_index
+    }
+
+    // adds value to leaf and everything above it, negative to take it back off
+    pub fn add_weight(&mut self, leaf: usize, event: PerfEventType, value: i64) {
+        let mut current_index = leaf;
+        loop {
+            let weight = self.nodes[current_index].weights.entry(event).or_insert(0);
+            *weight = weight.saturating_add_signed(value);
+
+            if current_index == 0 { break }
+            current_index = self.nodes[current_index].parent;
+        }
+    }
+
+    // like add_weight but for the counters, which stop short of the root the same way update does
+    pub fn add_count(&mut self, leaf: usize, event: PerfEventType, count: u32) {
+        let mut current_index = leaf;
+        while current_index != 0 {
+            *self.nodes[current_index].counters.entry(event).or_insert(0) += count;
+            current_index = self.nodes[current_index].parent;
         }
     }
 
+    // the weighted part of putting a sample into the tree
+    pub fn record_weight(&mut self, sample: &TimedSample) {
+        if sample.event.weighted() {
+            self.add_weight(sample.leaf, sample.event, sample.value as i64);
+        }
+    }
+
+    // the opposite of update, innermost frame first
+    pub fn trace_of(&self, leaf: usize) -> Vec<String> {
+        let mut trace: Vec<String> = Vec::new();
+        let mut current_index = leaf;
+
+        while current_index != 0 {
+            trace.push(self.nodes[current_index].name.clone());
+            current_index = self.nodes[current_index].parent;
+        }
+
+        trace
+    }
+
+    // builds a fresh tree out of only the given samples, and hands back the samples pointing into it
+    pub fn rebuild(&self, samples: &[TimedSample]) -> (Tree, Vec<TimedSample>) {
+        let mut tree = Tree::new();
+        let mut rebuilt: Vec<TimedSample> = Vec::new();
+        for sample in samples {
+            let leaf = if sample.leaf == 0 { 0 } else { tree.update(self.trace_of(sample.leaf), sample.event) };
+            let sample = TimedSample { leaf, ..*sample };
+            tree.record_weight(&sample);
+            rebuilt.push(sample);
+        }
+
+        tree.update_sorted_cache();
+        (tree, rebuilt)
+    }
+
     pub fn update_sorted_cache(&mut self) {
         let mut cache: Vec<(String, u64, usize)> = Vec::new();
 
         for child in &self.nodes[self.focused_node].children {
-            cache.push((child.0[child.0.find(":").unwrap()+1..].to_string(), *self.nodes[*child.1].counters.get(&self.focused_event).unwrap_or(&self.nodes[*child.1].counter(self.focused_event)) as u64, *child.1))
+            cache.push((child.0[child.0.find(":").unwrap()+1..].to_string(), self.nodes[*child.1].counter(self.focused_event), *child.1))
         } // this looks so funny im leaving it in 🥀
 
         cache.sort_by_key(|item| Reverse(item.1));
 
+        // the focused node can have fewer children for the new event (or none in an empty tree)
+        self.selected_node = self.selected_node.min(cache.len().saturating_sub(1));
+        self.display_head_node = self.display_head_node.min(cache.len().saturating_sub(1));
         self.focused_children_sorted_cache = cache;
     }
+
+    // node indices are only meaningful inside the tree they came from, so we walk both trees
+    // from the root together and match children up by name instead. returns where each of the
+    // other trees nodes ended up in this one
+    pub fn merge(&mut self, other: &Tree) -> Vec<usize> {
+        let mut to_visit: Vec<(usize, usize)> = vec![(0, 0)];
+        let mut index_map: Vec<usize> = vec![0; other.nodes.len()];
+
+        while let Some((other_index, self_index)) = to_visit.pop() {
+            let other_node = &other.nodes[other_index];
+            index_map[other_index] = self_index;
+
+            self.nodes[self_index].hits += other_node.hits;
+            for (event, count) in &other_node.counters {
+                *self.nodes[self_index].counters.entry(*event).or_insert(0) += count;
+            }
+            for (event, weight) in &other_node.weights {
+                *self.nodes[self_index].weights.entry(*event).or_insert(0) += weight;
+            }
+
+            for (name, &other_child) in &other_node.children {
+                let self_child = if let Some(&child_index) = self.nodes[self_index].children.get(name) { child_index }
+                else {
+                    let new_child_index = self.nodes.len();
+                    self.nodes.push(Node {
+                        name: name.clone(),
+                        counters: HashMap::new(),
+                        weights: HashMap::new(),
+                        hits: 0,
+                        children: HashMap::new(),
+                        parent: self_index,
+                    });
+                    self.nodes[self_index].children.insert(name.clone(), new_child_index);
+                    new_child_index
+                };
+
+                to_visit.push((other_child, self_child));
+            }
+        }
+
+        self.update_sorted_cache();
+        index_map
+    }
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Tree::new()
+    }
+}
+
+// every other part of the profile is aggregated as it comes in, but the samples themselves are kept
+// around to rebuild the tree for a time window. thats the part that grows with the length of the run,
+// so older ones get dropped once we have this many (about 80MB worth)
+pub const MAX_SAMPLES: usize = 1_000_000;
+
+// renumbered pids go above anything the kernel can hand out (PID_MAX_LIMIT) so they cant run into a real one
+const RENUMBERED_PID_START: u32 = 1 << 22;
+
+impl SaveData {
+    pub fn new() -> Self {
+        SaveData {
+            tree: Tree::new(),
+            data: HashMap::new(),
+            timeline: Timeline::new(),
+            samples: Vec::new(),
+            histograms: HashMap::new(),
+            event_labels: HashMap::new(),
+            sampling: HashMap::new(),
+            counts: HashMap::new(),
+            cgroups: HashMap::new(),
+            breakdowns: HashMap::new(),
+            sockets: HashMap::new(),
+        }
+    }
+
+    // everything we keep track of for one sample, trace is the symbolized stack if it had one
+    pub fn record(&mut self, sample: &PerfSample, trace: Option<Vec<String>>) -> usize {
+        // tcp samples come with the whole connection, which gets boiled down to a key here
+        let mut sample = *sample;
+        if sample.socket.family != 0 {
+            sample.key = socket_key(&sample.socket);
+            self.sockets.insert(sample.key, sample.socket);
+        }
+        let sample = &sample;
+
+        self.name_cgroup(sample);
+
+        let leaf = match trace {
+            Some(trace) => self.tree.update(trace, sample.event_type),
+            None => 0,
+        };
+
+        record_sample(&mut self.data, sample, 1);
+        self.timeline.record(sample);
+        let timed = TimedSample::new(sample, leaf);
+        self.tree.record_weight(&timed);
+        self.samples.push(timed);
+        // dropping in chunks so we arent shifting the whole vec over on every sample
+        if self.samples.len() >= MAX_SAMPLES + MAX_SAMPLES / 8 {
+            self.trim_samples();
+        }
+
+        if sample.event_type.valued() {
+            self.histograms.entry(sample.pid).or_insert_with(HashMap::new).entry(sample.event_type).or_insert_with(Histogram::default).record(sample.value);
+        }
+        if sample.event_type.keyed() {
+            record_keyed(&mut self.breakdowns, sample.event_type, sample.key, sample.pid, sample.value, sample.failed);
+        }
+
+        leaf
+    }
+
+    // allocations come out of ebpf already added up per thread and stack (PerfManager::read_allocs), so
+    // instead of going through record they only add to the tree, the per pid totals and the size
+    // histograms. that also means they arent in the samples and time windows leave them out
+    pub fn record_allocs(&mut self, sample: &PerfSample, allocs: &AllocStats, trace: Option<Vec<String>>) {
+        self.name_cgroup(sample);
+
+        let leaf = match trace {
+            Some(trace) => self.tree.update_by(trace, PerfEventType::Alloc, allocs.allocs as u32),
+            None => 0,
+        };
+        self.tree.add_count(leaf, PerfEventType::Free, allocs.frees as u32);
+        self.tree.add_weight(leaf, PerfEventType::Alloc, allocs.bytes as i64);
+        self.tree.add_weight(leaf, PerfEventType::LiveBytes, allocs.bytes as i64 - allocs.freed_bytes as i64);
+
+        for (event, count) in [(PerfEventType::Alloc, allocs.allocs), (PerfEventType::Free, allocs.frees)] {
+            if count != 0 { record_sample(&mut self.data, &PerfSample { event_type: event, ..*sample }, count as u32) }
+        }
+
+        if allocs.allocs != 0 {
+            let sizes = Histogram {
+                buckets: allocs.sizes.iter().map(|count| *count as u64).collect(),
+                count: allocs.allocs,
+                sum: allocs.bytes,
+                max: allocs.max,
+            };
+            self.histograms.entry(sample.pid).or_insert_with(HashMap::new).entry(PerfEventType::Alloc).or_insert_with(Histogram::default).merge(&sizes);
+        }
+    }
+
+    // only the first sample from each cgroup has to go look it up
+    fn name_cgroup(&mut self, sample: &PerfSample) {
+        if sample.cgroup != 0 && !self.cgroups.contains_key(&sample.cgroup) {
+            self.cgroups.insert(sample.cgroup, cgroup::cgroup_name(sample.pid).unwrap_or(format!("cgroup {}", sample.cgroup)));
+        }
+    }
+
+    pub fn trim_samples(&mut self) {
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.drain(..self.samples.len() - MAX_SAMPLES);
+        }
+    }
+
+    pub fn label(&self, event: PerfEventType) -> String {
+        self.event_labels.get(&event).cloned().unwrap_or(event.name())
+    }
+
+    // every pid (or tid) that shows up anywhere in the profile
+    fn pids(&self) -> HashSet<u32> {
+        let mut pids: HashSet<u32> = self.data.keys().chain(self.timeline.per_pid.keys()).chain(self.histograms.keys()).copied().collect();
+        pids.extend(self.samples.iter().flat_map(|sample| [sample.pid, sample.tgid]));
+        pids.extend(self.counts.keys().filter_map(|(_, pid)| *pid));
+        pids
+    }
+
+    // moves everything recorded under a pid in the map over to the pid it maps to
+    fn renumber_pids(&mut self, pid_map: &HashMap<u32, u32>) {
+        let remap = |pid: u32| pid_map.get(&pid).copied().unwrap_or(pid);
+
+        self.data = self.data.drain().map(|(pid, data)| (remap(pid), data)).collect();
+        self.timeline.per_pid = self.timeline.per_pid.drain().map(|(pid, events)| (remap(pid), events)).collect();
+        self.histograms = self.histograms.drain().map(|(pid, events)| (remap(pid), events)).collect();
+        self.counts = self.counts.drain().map(|((event, pid), count)| ((event, pid.map(remap)), count)).collect();
+        for sample in &mut self.samples {
+            sample.pid = remap(sample.pid);
+            sample.tgid = remap(sample.tgid);
+        }
+        for keys in self.breakdowns.values_mut() {
+            for pids in keys.values_mut() {
+                *pids = pids.drain().map(|(pid, stats)| (remap(pid), stats)).collect();
+            }
+        }
+    }
+
+    // moves everything recorded under an event in the map over to the event it maps to
+    fn renumber_events(&mut self, event_map: &HashMap<PerfEventType, PerfEventType>) {
+        let remap = |event: PerfEventType| event_map.get(&event).copied().unwrap_or(event);
+        fn remap_keys<T>(map: &mut HashMap<PerfEventType, T>, remap: impl Fn(PerfEventType) -> PerfEventType) {
+            *map = map.drain().map(|(event, value)| (remap(event), value)).collect();
+        }
+
+        for node in &mut self.tree.nodes {
+            remap_keys(&mut node.counters, remap);
+            remap_keys(&mut node.weights, remap);
+        }
+        for sample in &mut self.samples {
+            sample.event = remap(sample.event);
+        }
+        remap_keys(&mut self.timeline.totals, remap);
+        for events in self.timeline.per_pid.values_mut() {
+            remap_keys(events, remap);
+        }
+        for events in self.histograms.values_mut() {
+            remap_keys(events, remap);
+        }
+        for data in self.data.values_mut() {
+            remap_keys(&mut data.events, remap);
+        }
+        remap_keys(&mut self.breakdowns, remap);
+        remap_keys(&mut self.event_labels, remap);
+        remap_keys(&mut self.sampling, remap);
+        self.counts = self.counts.drain().map(|((event, pid), count)| ((remap(event), pid), count)).collect();
+    }
+
+    // slots get handed out per run, so uprobe_0 can be SSL_read in one profile and malloc in the other.
+    // a slot of others that means something else here moves to the slot we have the same thing in, or
+    // else to one thats free in both
+    fn slot_map(&self, other: &SaveData) -> Result<HashMap<PerfEventType, PerfEventType>> {
+        let mut moving: Vec<(&PerfEventType, &String)> = other.event_labels.iter()
+            .filter(|(event, label)| event.slot().is_some() && self.event_labels.get(event).is_some_and(|ours| ours != *label))
+            .collect();
+        moving.sort_by_key(|(event, _)| u8::from(**event));
+
+        let mut event_map: HashMap<PerfEventType, PerfEventType> = HashMap::new();
+        for &(event, label) in &moving {
+            let Some(slot) = event.slot() else { continue };
+            let kind_start = u8::from(*event) - slot;
+            let slots: Vec<PerfEventType> = (0..).map_while(|slot| PerfEventType::try_from(kind_start + slot).ok().filter(|other| other.slot() == Some(slot))).collect();
+            // slots other is moving out of are fair game too
+            let unused = |candidate: &PerfEventType| (!other.event_labels.contains_key(candidate) || moving.iter().any(|(moved, _)| *moved == candidate))
+                && !event_map.values().any(|taken| taken == candidate);
+
+            let target = slots.iter().find(|candidate| self.event_labels.get(*candidate) == Some(label) && unused(candidate))
+                .or_else(|| slots.iter().find(|candidate| !self.event_labels.contains_key(*candidate) && unused(candidate)))
+                .ok_or(anyhow::Error::msg(format!("cant merge, {label} needs a slot like {} but they are all taken by other things", event.name())))?;
+            event_map.insert(*event, *target);
+        }
+
+        Ok(event_map)
+    }
+
+    // profiles from other hosts or runs reuse the same pids for unrelated processes, so anything
+    // in other that collides with a pid we already have gets a fresh number instead of being lumped
+    // in with it. pid 0 is the idle task everywhere so it stays put. cgroup ids get the same
+    // treatment unless they point at the same cgroup path, and slotted events the same unless they
+    // were attached to the same thing
+    pub fn merge(&mut self, mut other: SaveData) -> Result<()> {
+        let event_map = self.slot_map(&other)?;
+        other.renumber_events(&event_map);
+
+        let our_pids = self.pids();
+        let other_pids = other.pids();
+        let mut next_pid = our_pids.iter().chain(other_pids.iter()).copied().max().unwrap_or(0).max(RENUMBERED_PID_START - 1) + 1;
+        let mut pid_map: HashMap<u32, u32> = HashMap::new();
+        for pid in other_pids.into_iter().filter(|pid| *pid != 0 && our_pids.contains(pid)) {
+            pid_map.insert(pid, next_pid);
+            next_pid += 1;
+        }
+        other.renumber_pids(&pid_map);
+
+        let mut next_cgroup = self.cgroups.keys().chain(other.cgroups.keys()).copied().max().unwrap_or(0) + 1;
+        let mut cgroup_map: HashMap<u64, u64> = HashMap::new();
+        for (cgroup, name) in &other.cgroups {
+            if self.cgroups.get(cgroup).is_some_and(|our_name| our_name != name) {
+                cgroup_map.insert(*cgroup, next_cgroup);
+                next_cgroup += 1;
+            }
+        }
+        other.cgroups = other.cgroups.drain().map(|(cgroup, name)| (cgroup_map.get(&cgroup).copied().unwrap_or(cgroup), name)).collect();
+        for data in other.data.values_mut() {
+            data.cgroup = cgroup_map.get(&data.cgroup).copied().unwrap_or(data.cgroup);
+        }
+
+        let index_map = self.tree.merge(&other.tree);
+        self.timeline.merge(&other.timeline);
+
+        // same deal as the timeline, samples get lined up by how far into the recording they were
+        for sample in &other.samples {
+            self.samples.push(TimedSample {
+                timestamp: sample.timestamp.saturating_sub(other.timeline.start_ns) + self.timeline.start_ns,
+                leaf: index_map[sample.leaf],
+                ..*sample
+            });
+        }
+        self.samples.sort_by_key(|sample| sample.timestamp);
+        self.trim_samples();
+
+        for (pid, events) in &other.histograms {
+            let entry = self.histograms.entry(*pid).or_insert_with(HashMap::new);
+            for (event, histogram) in events {
+                entry.entry(*event).or_insert_with(Histogram::default).merge(histogram);
+            }
+        }
+
+        for (event, keys) in &other.breakdowns {
+            for (key, pids) in keys {
+                for (pid, stats) in pids {
+                    self.breakdowns.entry(*event).or_insert_with(HashMap::new).entry(*key).or_insert_with(HashMap::new)
+                        .entry(*pid).or_insert_with(KeyedStats::default).merge(stats);
+                }
+            }
+        }
+
+        self.sockets.extend(other.sockets);
+        self.cgroups.extend(other.cgroups);
+
+        self.event_labels.extend(other.event_labels);
+
+        // profiles that ran the same event differently cant really be scaled together, first one wins
+        for (event, sampling) in other.sampling {
+            self.sampling.entry(event).or_insert(sampling);
+        }
+
+        for (key, count) in &other.counts {
+            self.counts.entry(*key).or_default().merge(count);
+        }
+
+        for (pid, other_data) in other.data {
+            let entry = self.data.entry(pid).or_insert_with(|| ProfileData { name: other_data.name.clone(), uid: other_data.uid, gid: other_data.gid, cgroup: other_data.cgroup, ns_pid: other_data.ns_pid, events: HashMap::new() });
+
+            for (event, count) in other_data.events {
+                *entry.events.entry(event).or_insert(0) += count;
+            }
+        }
+
+        Ok(())
+    }
+
+    // a copy of the profile with only what happened between from_ns and to_ns after the start of the recording
+    pub fn window(&self, from_ns: u64, to_ns: u64) -> SaveData {
+        let from = self.timeline.start_ns + from_ns;
+        let to = self.timeline.start_ns + to_ns;
+        let in_window: Vec<TimedSample> = self.samples.iter().filter(|sample| sample.timestamp >= from && sample.timestamp < to).copied().collect();
+
+        let (tree, samples) = self.tree.rebuild(&in_window);
+        let mut timeline = Timeline::starting_at(from, self.timeline.bucket_ns);
+        let mut data: HashMap<u32, ProfileData> = HashMap::new();
+        let mut histograms: HashMap<u32, HashMap<PerfEventType, Histogram>> = HashMap::new();
+        let mut breakdowns: HashMap<PerfEventType, HashMap<u64, HashMap<u32, KeyedStats>>> = HashMap::new();
+
+        for sample in &samples {
+            timeline.record_at(sample.timestamp, sample.pid, sample.event);
+            if sample.event.valued() {
+                histograms.entry(sample.pid).or_insert_with(HashMap::new).entry(sample.event).or_insert_with(Histogram::default).record(sample.value);
+            }
+            if sample.event.keyed() {
+                record_keyed(&mut breakdowns, sample.event, sample.key, sample.pid, sample.value, sample.failed);
+            }
+
+            let entry = data.entry(sample.pid).or_insert_with(|| match self.data.get(&sample.pid) {
+                Some(original) => ProfileData { name: original.name.clone(), uid: original.uid, gid: original.gid, cgroup: original.cgroup, ns_pid: original.ns_pid, events: HashMap::new() },
+                None => ProfileData { name: String::new(), uid: 0, gid: 0, cgroup: 0, ns_pid: 0, events: HashMap::new() },
+            });
+            *entry.events.entry(sample.event).or_insert(0) += 1;
+        }
+
+        // counters only have the one total, so the window gets the share of it that its samples make up
+        let counts = self.counts.iter().map(|((event, pid), count)| {
+            let share = hits(&data, *event, *pid) as f64 / hits(&self.data, *event, *pid).max(1) as f64;
+            ((*event, *pid), EventCount { value: (count.value as f64 * share) as u64, enabled_ns: count.enabled_ns.min(to_ns.saturating_sub(from_ns)) })
+        }).collect();
+
+        SaveData { tree, data, timeline, samples, histograms, event_labels: self.event_labels.clone(), sampling: self.sampling.clone(), counts, cgroups: self.cgroups.clone(), breakdowns, sockets: self.sockets.clone() }
+    }
+
+    // how many events the thread pid actually had, going by its own count if it was running when we
+    // attached with -p and otherwise its share of the samples applied to the system wide count. threads
+    // started after that only show up in their parents count once they exit, so they get none
+    pub fn estimate(&self, pid: u32, event: PerfEventType) -> Option<EventCount> {
+        self.estimate_from_hits(pid, event, hits(&self.data, event, Some(pid)), hits(&self.data, event, None))
+    }
+
+    // estimate for every pid and event it has samples of, adding up everyones hits just once instead of
+    // for each of them
+    pub fn estimates(&self) -> HashMap<(u32, PerfEventType), EventCount> {
+        let mut all_hits: HashMap<PerfEventType, u64> = HashMap::new();
+        for profile in self.data.values() {
+            for (event, count) in &profile.events {
+                *all_hits.entry(*event).or_insert(0) += *count as u64;
+            }
+        }
+
+        let mut estimates = HashMap::new();
+        for (pid, profile) in &self.data {
+            for (event, count) in &profile.events {
+                if let Some(estimate) = self.estimate_from_hits(*pid, *event, *count as u64, all_hits[event]) {
+                    estimates.insert((*pid, *event), estimate);
+                }
+            }
+        }
+        estimates
+    }
+
+    fn estimate_from_hits(&self, pid: u32, event: PerfEventType, pid_hits: u64, all_hits: u64) -> Option<EventCount> {
+        if let Some(count) = self.counts.get(&(event, Some(pid))) {
+            return Some(*count)
+        }
+
+        let total = self.counts.get(&(event, None))?;
+        let share = pid_hits as f64 / all_hits.max(1) as f64;
+        Some(EventCount { value: (total.value as f64 * share) as u64, enabled_ns: total.enabled_ns })
+    }
+
+    // every pid's stats for one key of a keyed event added together
+    pub fn breakdown_totals(&self, event: PerfEventType) -> Vec<(u64, KeyedStats)> {
+        let Some(keys) = self.breakdowns.get(&event) else { return Vec::new() };
+        keys.iter().map(|(key, pids)| {
+            let mut total = KeyedStats::default();
+            for stats in pids.values() {
+                total.merge(stats);
+            }
+            (*key, total)
+        }).collect()
+    }
+}
+
+impl Default for SaveData {
+    fn default() -> Self {
+        SaveData::new()
+    }
+}
+
+// samples of event from pid, or from everyone
+fn hits(data: &HashMap<u32, ProfileData>, event: PerfEventType, pid: Option<u32>) -> u64 {
+    data.iter()
+        .filter(|(data_pid, _)| pid.is_none_or(|pid| pid == **data_pid))
+        .filter_map(|(_, profile)| profile.events.get(&event))
+        .map(|count| *count as u64)
+        .sum()
+}
+
+// fnv-1a over the fields of the tuple, spelled out so the key stays the same across runs, merges and
+// rust versions
+fn socket_key(socket: &SocketTuple) -> u64 {
+    let mut bytes: Vec<u8> = Vec::with_capacity(38);
+    bytes.extend(socket.family.to_le_bytes());
+    bytes.extend(socket.sport.to_le_bytes());
+    bytes.extend(socket.dport.to_le_bytes());
+    bytes.extend(socket.saddr);
+    bytes.extend(socket.daddr);
+
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+fn record_keyed(breakdowns: &mut HashMap<PerfEventType, HashMap<u64, HashMap<u32, KeyedStats>>>, event: PerfEventType, key: u64, pid: u32, value: u64, failed: bool) {
+    breakdowns.entry(event).or_insert_with(HashMap::new).entry(key).or_insert_with(HashMap::new)
+        .entry(pid).or_insert_with(KeyedStats::default).record(value, failed);
 }
 
 impl Widget for &Tree {
@@ -110,14 +685,303 @@ impl Widget for &Tree {
     }
 }
 
+pub fn record_sample(profile_data: &mut HashMap<u32, ProfileData>, sample: &PerfSample, count: u32) {
+    let profile_data_entry = profile_data.entry(sample.pid).or_insert_with(||
+        ProfileData {
+            events: HashMap::new(),
+            name: String::from_utf8_lossy(&sample.cmd).to_string(),
+            uid: 0,
+            gid: 0,
+            cgroup: 0,
+            ns_pid: 0,
+        }
+    );
+
+    // increment the counter for that event
+    *profile_data_entry.events.entry(sample.event_type).or_insert(0) += count;
+    profile_data_entry.uid = sample.uid;
+    profile_data_entry.gid = sample.gid;
+    profile_data_entry.cgroup = sample.cgroup;
+    profile_data_entry.ns_pid = sample.ns_pid;
+}
+
+// saved profiles start with SAVE_MAGIC and the format version as a little endian u32, followed by
+// the bincoded SaveData. bump SAVE_VERSION whenever anything that ends up in SaveData changes shape,
+// files from before there was a header are the original tree and per pid data only
+const SAVE_MAGIC: &[u8; 8] = b"FLXTRACE";
+const SAVE_VERSION: u32 = 2;
+
 pub fn save_traces(path: String, data: SaveData) -> Result<()> {
-    let ser = encode_to_vec(data, config::standard())?;
+    let mut ser = SAVE_MAGIC.to_vec();
+    ser.extend(SAVE_VERSION.to_le_bytes());
+    ser.extend(encode_to_vec(data, config::standard())?);
     write(path, ser)?;
     Ok(())
 }
 
 pub fn read_traces_file(path: String) -> Result<SaveData> {
     let bytes = read(path)?;
-    let de: (SaveData, usize) = decode_from_slice(&bytes, config::standard())?;
+    decode_traces(&bytes)
+}
+
+fn decode_traces(bytes: &[u8]) -> Result<SaveData> {
+    let Some(rest) = bytes.strip_prefix(SAVE_MAGIC) else {
+        let de: (LegacySaveData, usize) = decode_from_slice(bytes, config::standard())?;
+        return Ok(de.0.into())
+    };
+
+    let version = u32::from_le_bytes(rest.get(..4).ok_or(FlextraceError::BadSaveFormat(String::from("file ends in the middle of the header")))?.try_into()?);
+    if version != SAVE_VERSION {
+        return Err(FlextraceError::BadSaveFormat(format!("format version {version}, expected version {SAVE_VERSION}")).into())
+    }
+
+    let de: (SaveData, usize) = decode_from_slice(&rest[4..], config::standard())?;
     Ok(de.0)
 }
+
+// the layout from before saves had a header, kept around so those files still open
+#[derive(Decode)]
+struct LegacySaveData {
+    tree: LegacyTree,
+    data: HashMap<u32, LegacyProfileData>,
+}
+
+#[derive(Decode)]
+struct LegacyTree {
+    nodes: Vec<LegacyNode>,
+    focused_event: PerfEventType,
+    focused_node: usize,
+    selected_node: usize,
+    focused_children_sorted_cache: Vec<(String, u64, usize)>,
+    display_head_node: usize,
+}
+
+#[derive(Decode)]
+struct LegacyNode {
+    counters: HashMap<PerfEventType, u32>,
+    name: String,
+    children: HashMap<String, usize>,
+    hits: u32,
+    parent: usize,
+}
+
+#[derive(Decode)]
+struct LegacyProfileData {
+    name: String,
+    gid: u32,
+    events: HashMap<PerfEventType, u32>,
+}
+
+impl From<LegacySaveData> for SaveData {
+    fn from(legacy: LegacySaveData) -> Self {
+        let tree = Tree {
+            nodes: legacy.tree.nodes.into_iter().map(|node| Node { counters: node.counters, weights: HashMap::new(), name: node.name, children: node.children, hits: node.hits, parent: node.parent }).collect(),
+            focused_event: legacy.tree.focused_event,
+            focused_node: legacy.tree.focused_node,
+            selected_node: legacy.tree.selected_node,
+            focused_children_sorted_cache: legacy.tree.focused_children_sorted_cache,
+            display_head_node: legacy.tree.display_head_node,
+        };
+        let data = legacy.data.into_iter().map(|(pid, data)| (pid, ProfileData { name: data.name, uid: 0, gid: data.gid, cgroup: 0, ns_pid: 0, events: data.events })).collect();
+
+        // theres no timing information in these, so the timeline is just empty
+        SaveData { tree, data, timeline: Timeline::starting_at(0, timeline::DEFAULT_BUCKET_NS), ..SaveData::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flextrace_common::{ALLOC_SIZE_BUCKETS, UPROBE_SLOTS};
+
+    use super::*;
+
+    fn sample(pid: u32, comm: &str) -> PerfSample {
+        let mut cmd = [0u8; 16];
+        cmd[..comm.len()].copy_from_slice(comm.as_bytes());
+        PerfSample { event_type: PerfEventType::CpuClock, pid, tgid: pid, cmd, ..Default::default() }
+    }
+
+    #[test]
+    fn estimates_split_the_system_wide_count_by_hits() {
+        let mut profile = SaveData::new();
+        for _ in 0..3 { profile.record(&sample(1, "a"), None); }
+        profile.record(&sample(2, "b"), None);
+        profile.counts.insert((PerfEventType::CpuClock, None), EventCount { value: 1000, enabled_ns: 1_000_000_000 });
+        profile.counts.insert((PerfEventType::CpuClock, Some(2)), EventCount { value: 300, enabled_ns: 1_000_000_000 });
+
+        let estimates = profile.estimates();
+        assert_eq!(estimates.len(), 2);
+        assert_eq!(estimates[&(1, PerfEventType::CpuClock)].value, 750);
+        assert_eq!(estimates[&(2, PerfEventType::CpuClock)].value, 300);
+        for ((pid, event), estimate) in &estimates {
+            assert_eq!(profile.estimate(*pid, *event).map(|estimate| estimate.value), Some(estimate.value));
+        }
+    }
+
+    #[test]
+    fn merge_renumbers_colliding_pids() {
+        let mut first = SaveData::new();
+        first.record(&sample(100, "nginx"), Some(vec!["a:main(at)1".to_string()]));
+        let mut second = SaveData::new();
+        second.record(&sample(100, "postgres"), Some(vec!["a:main(at)1".to_string()]));
+        second.record(&sample(0, "swapper"), None);
+
+        first.merge(second).unwrap();
+
+        assert_eq!(first.data.len(), 3);
+        assert_eq!(first.data[&100].name.trim_end_matches('\0'), "nginx");
+        let renumbered = first.data.iter().find(|(_, data)| data.name.starts_with("postgres")).map(|(pid, _)| *pid).unwrap();
+        assert!(renumbered >= RENUMBERED_PID_START);
+        assert!(first.data.contains_key(&0));
+        assert!(first.samples.iter().any(|sample| sample.pid == renumbered && sample.tgid == renumbered));
+        assert_eq!(first.tree.nodes[1].hits, 2);
+    }
+
+    #[test]
+    fn merge_renumbers_cgroups_with_other_paths() {
+        let mut first = SaveData::new();
+        first.cgroups.insert(7, "/system.slice/a.service".to_string());
+        let mut second = SaveData::new();
+        second.cgroups.insert(7, "/system.slice/b.service".to_string());
+        second.record(&sample(5, "b"), None);
+        second.data.get_mut(&5).unwrap().cgroup = 7;
+
+        first.merge(second).unwrap();
+
+        assert_eq!(first.cgroups[&7], "/system.slice/a.service");
+        assert_eq!(first.cgroups[&8], "/system.slice/b.service");
+        assert_eq!(first.data[&5].cgroup, 8);
+    }
+
+    #[test]
+    fn merge_moves_slots_attached_to_other_things() {
+        let uprobe = |slot: u8, pid: u32| PerfSample { event_type: PerfEventType::uprobe(slot).unwrap(), value: 10, ..sample(pid, "app") };
+        let mut first = SaveData::new();
+        first.event_labels.insert(PerfEventType::Uprobe0, "libssl.so:SSL_read".to_string());
+        first.event_labels.insert(PerfEventType::Uprobe1, "libc.so:malloc".to_string());
+        first.record(&uprobe(0, 1), None);
+        let mut second = SaveData::new();
+        second.event_labels.insert(PerfEventType::Uprobe0, "libc.so:malloc".to_string());
+        second.event_labels.insert(PerfEventType::Uprobe1, "libc.so:free".to_string());
+        second.record(&uprobe(0, 2), None);
+        second.record(&uprobe(1, 2), None);
+
+        first.merge(second).unwrap();
+
+        assert_eq!(first.label(PerfEventType::Uprobe0), "libssl.so:SSL_read");
+        assert_eq!(first.label(PerfEventType::Uprobe1), "libc.so:malloc");
+        assert_eq!(first.label(PerfEventType::Uprobe2), "libc.so:free");
+        assert_eq!(first.data[&1].events[&PerfEventType::Uprobe0], 1);
+        assert_eq!(first.data[&2].events[&PerfEventType::Uprobe1], 1);
+        assert_eq!(first.data[&2].events[&PerfEventType::Uprobe2], 1);
+        assert!(!first.data[&2].events.contains_key(&PerfEventType::Uprobe0));
+        assert_eq!(first.histograms[&2][&PerfEventType::Uprobe2].count, 1);
+
+        let mut full = SaveData::new();
+        let mut other = SaveData::new();
+        for slot in 0..UPROBE_SLOTS {
+            full.event_labels.insert(PerfEventType::uprobe(slot).unwrap(), format!("a:{slot}"));
+            other.event_labels.insert(PerfEventType::uprobe(slot).unwrap(), format!("b:{slot}"));
+        }
+        assert!(full.merge(other).is_err());
+    }
+
+    #[test]
+    fn zero_latency_calls_still_count() {
+        let mut save = SaveData::new();
+        save.record(&PerfSample { event_type: PerfEventType::Uprobe0, value: 0, ..sample(9, "app") }, None);
+        save.record(&PerfSample { event_type: PerfEventType::Uprobe0, value: 500, ..sample(9, "app") }, None);
+        save.record(&sample(9, "app"), None);
+
+        assert_eq!(save.histograms[&9][&PerfEventType::Uprobe0].count, 2);
+        assert!(!save.histograms[&9].contains_key(&PerfEventType::CpuClock));
+    }
+
+    #[test]
+    fn records_added_up_allocations() {
+        let mut save = SaveData::new();
+        let trace = || Some(vec!["libc:malloc(at)1".to_string(), "app:main(at)2".to_string()]);
+        let mut sizes = [0u32; ALLOC_SIZE_BUCKETS];
+        sizes[5] = 3; // 16..32 bytes
+        save.record_allocs(&sample(9, "app"), &AllocStats { allocs: 3, bytes: 60, frees: 1, freed_bytes: 20, max: 20, sizes, ..Default::default() }, trace());
+        save.record_allocs(&sample(9, "app"), &AllocStats { frees: 2, freed_bytes: 40, ..Default::default() }, trace());
+
+        let main = save.tree.nodes[0].children["app:main(at)2"];
+        let leaf = save.tree.nodes[main].children["libc:malloc(at)1"];
+        for node in [main, leaf] {
+            assert_eq!(save.tree.nodes[node].counters[&PerfEventType::Alloc], 3);
+            assert_eq!(save.tree.nodes[node].counters[&PerfEventType::Free], 3);
+        }
+        for node in [0, main, leaf] {
+            assert_eq!(save.tree.nodes[node].weights[&PerfEventType::Alloc], 60);
+            assert_eq!(save.tree.nodes[node].weights[&PerfEventType::LiveBytes], 0);
+        }
+
+        assert_eq!(save.data[&9].events[&PerfEventType::Alloc], 3);
+        assert_eq!(save.data[&9].events[&PerfEventType::Free], 3);
+        let histogram = &save.histograms[&9][&PerfEventType::Alloc];
+        assert_eq!((histogram.count, histogram.sum, histogram.buckets[5]), (3, 60, 3));
+        assert!(save.samples.is_empty());
+    }
+
+    #[test]
+    fn socket_keys_are_stable() {
+        let mut socket = SocketTuple { family: 2, sport: 8080, dport: 443, ..Default::default() };
+        socket.saddr[..4].copy_from_slice(&[10, 0, 0, 1]);
+        socket.daddr[..4].copy_from_slice(&[10, 0, 0, 2]);
+
+        // saved profiles and merges depend on this never changing
+        assert_eq!(socket_key(&socket), 0xa5343b29aaf624eb);
+        assert_eq!(socket_key(&SocketTuple { _pad: 7, ..socket }), socket_key(&socket));
+        assert_ne!(socket_key(&SocketTuple { dport: 444, ..socket }), socket_key(&socket));
+    }
+
+    #[test]
+    fn saves_round_trip_with_header() {
+        let mut save = SaveData::new();
+        save.record(&sample(42, "redis"), Some(vec!["a:main(at)1".to_string()]));
+        let mut bytes = SAVE_MAGIC.to_vec();
+        bytes.extend(SAVE_VERSION.to_le_bytes());
+        bytes.extend(encode_to_vec(save, config::standard()).unwrap());
+
+        let read = decode_traces(&bytes).unwrap();
+        assert_eq!(read.data[&42].events[&PerfEventType::CpuClock], 1);
+        assert_eq!(read.tree.nodes.len(), 2);
+
+        bytes[SAVE_MAGIC.len()] = 99;
+        let error = decode_traces(&bytes).err().unwrap().to_string();
+        assert!(error.contains(&format!("format version 99, expected version {SAVE_VERSION}")), "{error}");
+    }
+
+    #[derive(Encode)]
+    struct OldNode {
+        counters: HashMap<PerfEventType, u32>,
+        name: String,
+        children: HashMap<String, usize>,
+        hits: u32,
+        parent: usize,
+    }
+
+    #[derive(Encode)]
+    struct OldProfileData {
+        name: String,
+        gid: u32,
+        events: HashMap<PerfEventType, u32>,
+    }
+
+    #[test]
+    fn reads_saves_from_before_the_header() {
+        let nodes = vec![
+            OldNode { counters: HashMap::new(), name: "root".to_string(), children: HashMap::from([("a:main(at)1".to_string(), 1)]), hits: 0, parent: 0 },
+            OldNode { counters: HashMap::from([(PerfEventType::CacheMiss, 3)]), name: "a:main(at)1".to_string(), children: HashMap::new(), hits: 3, parent: 0 },
+        ];
+        let data = HashMap::from([(7u32, OldProfileData { name: "old".to_string(), gid: 100, events: HashMap::from([(PerfEventType::CacheMiss, 3)]) })]);
+        let tree = (nodes, PerfEventType::None, 0usize, 0usize, Vec::<(String, u64, usize)>::new(), 0usize);
+        let bytes = encode_to_vec((tree, data), config::standard()).unwrap();
+
+        let read = decode_traces(&bytes).unwrap();
+        assert_eq!(read.tree.nodes[1].counter(PerfEventType::CacheMiss), 3);
+        assert_eq!(read.data[&7].gid, 100);
+        assert_eq!(read.data[&7].name, "old");
+    }
+}
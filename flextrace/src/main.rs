@@ -1,19 +1,27 @@
-use std::io;
+use std::{fs, io::{self, Write}, net::SocketAddr, os::unix::fs::MetadataExt, time::Duration};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossterm::{event::{DisableMouseCapture, EnableMouseCapture}, execute, terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode}};
-use flextrace::{SaveData, save_traces};
-use flextrace_common::{PERF_EVENT_VARIANTS, PerfEventType};
+use flextrace::{SaveData, cgroup, export, perf_script, read_traces_file, save_traces};
+use flextrace_common::{EventMask, FlextraceError, PERF_EVENT_VARIANTS, PerfEventType, Sampling};
 //#[rustfmt::skip]
-use log::{LevelFilter, info};
+use log::{LevelFilter, debug, info};
 
+mod btf;
+mod counters;
+mod daemon;
+mod namespace;
 mod perf;
+mod perf_event;
+mod pmu;
+mod server;
 mod tui;
+mod usdt;
 
 use perf::*;
 use ratatui::{Terminal, prelude::CrosstermBackend};
 
-use crate::tui::{State, run_app};
+use crate::{daemon::{Retention, run_daemon}, server::start_server, tui::{State, run_app}};
 //use ratatui::{DefaultTerminal, crossterm::event};
 
 #[derive(Debug, Parser, Clone)]
@@ -30,31 +38,143 @@ struct Opt {
     #[arg(short, long, value_name = "PATH", help = "path to output profiling data after completing execution")]
     out: Option<String>,
 
-    #[arg(short, long, value_parser = parse_events, num_args = 1.., help = "list of perf events to profile with optional period, event:period", default_value = "all")]
-    events: Vec<(String, u64)>,
+    #[arg(short, long, value_parser = parse_events, num_args = 1.., help = "list of perf events to profile with an optional period (event:period) or frequency (event@99hz)\nevents default to 99hz for cycles and clocks and a fitting period for the rest\nkernel tracepoints work too as tracepoint:category:name, and raw pmu events as raw:0x1a2 or by vendor name (INST_RETIRED.ANY_P)", default_value = "all")]
+    events: Vec<(String, Option<Sampling>)>,
+
+    #[arg(long, value_name = "DIR", help = "json pmu event tables to look vendor event names up in, a copy of the kernels tools/perf/pmu-events/arch/<arch>\n(the right cpu model gets picked through its mapfile.csv) or one model directory out of it. intels architectural events are built in")]
+    pmu_events: Option<String>,
 
     #[arg(short = 'x', long, value_parser = parse_filter, help = "define events to ignore from certain processes: pid:event1,event2,event3\nor just the pid to drop everything from that process", default_value = "noarg")]
-    filter_exclude: Vec<(u32, u32)>,
+    filter_exclude: Vec<(u32, EventMask)>,
 
     #[arg(short = 'p', long, num_args = 1.., help = "explicitly declare processes to be profiled (if this flag is not used, the default is all processes)")]
     processes: Vec<u32>,
 
+    #[arg(long, value_name = "PID", num_args = 1.., help = "only let samples from these processes through, everything else is dropped in the kernel before it costs anything\nif more than one of the --allow options is given a process has to match all of them")]
+    allow_pid: Vec<u32>,
+
+    #[arg(long, value_name = "UID", num_args = 1.., help = "only let samples from processes running as these users through")]
+    allow_uid: Vec<u32>,
+
+    #[arg(long, visible_alias = "cgroup", value_name = "PATH", num_args = 1.., help = "only let samples from processes in these cgroups (or cgroups under them) through, e.g. /sys/fs/cgroup/system.slice/nginx.service")]
+    allow_cgroup: Vec<String>,
+
+    #[arg(long, value_name = "ID", num_args = 1.., help = "only let samples from processes in these containers through, the id can be shortened like with docker\nprocesses are grouped by container on the containers screen (g)")]
+    container: Vec<String>,
+
+    #[arg(long, value_name = "PREFIX", num_args = 1.., help = "only let samples from processes whose name starts with one of these through")]
+    allow_comm: Vec<String>,
+
     #[arg(short = 'f', long, num_args = 1.., help = "specify processes to return stack traces from upon perf event hit based on frame pointers (program MUST be compiled without frame pointer omission)")]
     stack_trace_fp: Vec<u32>,
 
+    #[arg(long, value_name = "PATH:SYMBOL", num_args = 1.., help = "count calls to and time a function in a binary or shared library, e.g. /usr/lib/libssl.so:SSL_read\nstack traces come from the processes given with -f")]
+    uprobe: Vec<String>,
+
+    #[arg(long, help = "time every syscall, broken down by syscall number on the syscalls screen (s)\nstack traces at syscall entry come from the processes given with -f", default_value_t = false)]
+    syscalls: bool,
+
+    #[arg(long, help = "time every block io request per device, attributed to the process that issued it, on the block io screen (b)\nstack traces at issue come from the processes given with -f", default_value_t = false)]
+    block_io: bool,
+
+    #[arg(long, help = "time how long threads block waiting on futexes (contended mutexes, condvars etc)\nfocus lock_wait in the tree for wait time per stack, stacks come from the processes given with -f", default_value_t = false)]
+    locks: bool,
+
+    #[arg(long, help = "count tcp bytes sent, received and retransmitted per process and connection, on the network screen (n)\nfocus tcp_send or tcp_recv in the tree for bytes per stack, stacks come from the processes given with -f", default_value_t = false)]
+    tcp: bool,
+
+    #[arg(long, value_name = "LIBRARY", num_args = 0..=1, default_missing_value = "libc", help = "track malloc/calloc/realloc/free and mmap/munmap in libc or the given allocator library\nfocus alloc_bytes or live_bytes in the tree, stacks come from the processes given with -f\nallocations get added up in the kernel and come in about once a second, time windows leave them out")]
+    alloc: Option<String>,
+
+    #[arg(long, value_name = "PATH:PROVIDER:NAME[:ARG]", num_args = 1.., help = "count hits of a usdt probe in a binary or shared library, e.g. /usr/bin/postgres:postgresql:query__start\nthe probes ARGth argument (1 by default, 0 for none) is recorded as the value")]
+    usdt: Vec<String>,
+
+    #[arg(long, value_name = "FUNCTION", num_args = 1.., help = "count calls to and time a kernel function, e.g. vfs_read\nkernel and user stack traces come from the processes given with -f")]
+    kprobe: Vec<String>,
+
     #[arg(long, help = "list perf events supported by flextrace (remove the event_ when using as an argument)", default_value_t = false)]
     list: bool,
+
+    #[arg(long, value_name = "[ADDR:]PORT", value_parser = parse_http_addr, help = "serve the live profile over http on this port of 127.0.0.1, or on ADDR (e.g. 7878 or 127.0.0.1:7878)\nroutes: /folded, /pprof, /tree.json, /processes.json, /metrics, add ?event=name to pick a counter")]
+    http: Option<SocketAddr>,
+
+    #[arg(long, requires = "http", default_value_t = false, help = "let --http listen on an address other than loopback, there is no authentication so anyone who can reach it can read the profile")]
+    http_public: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+// no subcommand means we just record like normal
+#[derive(Debug, Subcommand, Clone)]
+enum Command {
+    #[command(about = "combine several saved profiles into one, summing up all the counters")]
+    Merge {
+        #[arg(required = true, num_args = 1.., value_name = "PATH", help = "saved profiles to merge")]
+        inputs: Vec<String>,
+
+        #[arg(short, long, value_name = "PATH", help = "path to write the merged profile to")]
+        out: String,
+    },
+
+    #[command(about = "turn the text output of `perf script` into a flextrace profile")]
+    Import {
+        #[arg(value_name = "PATH", help = "file with the output of perf script in it")]
+        input: String,
+
+        #[arg(short, long, value_name = "PATH", help = "path to write the flextrace profile to")]
+        out: String,
+    },
+
+    #[command(about = "print a saved profile in another format, optionally only a slice of it in time")]
+    Report {
+        #[arg(value_name = "PATH", help = "saved profile to read")]
+        input: String,
+
+        #[arg(long, value_name = "SECONDS", help = "only use samples from this many seconds into the recording onwards")]
+        from: Option<f64>,
+
+        #[arg(long, value_name = "SECONDS", help = "only use samples from before this many seconds into the recording")]
+        to: Option<f64>,
+
+        #[arg(short, long, default_value = "folded", value_parser = ["folded", "pprof", "json", "processes", "chrome", "speedscope", "ft"], help = "output format, chrome is the chrome trace event format for ui.perfetto.dev, ft writes a flextrace profile again")]
+        format: String,
+
+        #[arg(short, long, value_name = "EVENT", default_value = "all", help = "which event to use the counters of for folded and pprof output, or the samples of for chrome output")]
+        event: String,
+
+        #[arg(short, long, value_name = "PATH", help = "where to write the report, defaults to stdout")]
+        out: Option<String>,
+    },
+
+    #[command(about = "keep profiling forever without the tui, writing a new profile to a directory every window")]
+    Daemon {
+        #[arg(short, long, value_name = "DIR", help = "directory to write the timestamped profiles into")]
+        dir: String,
+
+        #[arg(short, long, value_name = "SECONDS", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..), help = "how long each profile window lasts")]
+        interval: u64,
+
+        #[arg(long, value_name = "COUNT", help = "only keep this many of the newest profiles around")]
+        max_files: Option<usize>,
+
+        #[arg(long, value_name = "BYTES", help = "delete the oldest profiles once they take up more than this much space")]
+        max_bytes: Option<u64>,
+
+        #[arg(long, value_name = "SECONDS", help = "delete profiles older than this")]
+        max_age: Option<u64>,
+    },
 }
 
 // im pretty sure clap automaticlly handles the vec<> part and we
 // only have to worry about handling one str at a time
-fn parse_filter(filter: &str) -> anyhow::Result<(u32, u32)> {
+fn parse_filter(filter: &str) -> anyhow::Result<(u32, EventMask)> {
     if filter == "noarg" {
-        return Ok((0, 0u32));
+        return Ok((0, EventMask::default()));
     }
 
     if let Some(colon_index) = filter.find(":") {
-        let mut events_mask: u32 = 0;
+        let mut events_mask = EventMask::default();
         let mut events_index = 0;
 
         let mut to_process = &filter[colon_index + 1..];
@@ -62,7 +182,7 @@ fn parse_filter(filter: &str) -> anyhow::Result<(u32, u32)> {
         while let Some(comma_index) = to_process.find(",") {
             if events_index >= PERF_EVENT_VARIANTS { return Err(anyhow::Error::msg("too many perf events specified yo")); }
             
-            events_mask |= 1 << PerfEventType::from_str(&to_process[..comma_index].to_string()).unwrap() as u8;
+            events_mask.set(PerfEventType::from_str(&to_process[..comma_index].to_string())?.into());
 
             if comma_index != to_process.len() {
                 to_process = &to_process[comma_index + 1..];
@@ -75,7 +195,7 @@ fn parse_filter(filter: &str) -> anyhow::Result<(u32, u32)> {
         if to_process != "" && events_index >= PERF_EVENT_VARIANTS {
             return Err(anyhow::Error::msg("too many perf events mr white!!!"));
         }
-        events_mask |= 1 << PerfEventType::from_str(&to_process.to_string()).unwrap() as u8;
+        events_mask.set(PerfEventType::from_str(&to_process.to_string())?.into());
 
         let key = match filter[..colon_index].parse::<u32>() {
             Ok(thing) => thing,
@@ -84,21 +204,57 @@ fn parse_filter(filter: &str) -> anyhow::Result<(u32, u32)> {
 
         Ok((key, events_mask))
     }
-    else { Ok((filter.parse()?, u32::MAX as u32)) }
+    else { Ok((filter.parse()?, EventMask::all())) }
+}
+
+// cgroup v2 ids are the inode number of the cgroups directory, which is what bpf_get_current_cgroup_id gives back
+fn cgroup_id(path: &str) -> anyhow::Result<u64> {
+    let metadata = fs::metadata(path).map_err(|e| FlextraceError::BadArgument(format!("{path} ({e})")))?;
+    if !metadata.is_dir() { return Err(FlextraceError::BadArgument(format!("{path} is not a cgroup directory")).into()) }
+    Ok(metadata.ino())
+}
+
+// a bare port means loopback, so serving to the network has to be spelled out
+fn parse_http_addr(addr: &str) -> anyhow::Result<SocketAddr> {
+    if let Ok(port) = addr.parse::<u16>() {
+        return Ok(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+    Ok(addr.parse().map_err(|e| FlextraceError::BadArgument(format!("{addr} ({e})")))?)
 }
 
-fn parse_events(filter: &str) -> anyhow::Result<(String, u64)> {
+fn parse_events(filter: &str) -> anyhow::Result<(String, Option<Sampling>)> {
     if filter == "all" {
-        return Ok(("all".to_string(), 0u64))
+        return Ok(("all".to_string(), None))
     }
 
-    let event: (String, u64);
-    if let Some(colon_index) = filter.find(":") {
-        event = (filter[0..colon_index].to_string(), filter[colon_index + 1..].parse()?);
+    // tracepoints have colons of their own and no period since every hit counts
+    if filter.starts_with("tracepoint:") {
+        return Ok((filter.to_string(), None))
+    }
+
+    // event@99hz samples by frequency instead of every so many events
+    if let Some((event, frequency)) = filter.split_once("@") {
+        let frequency = frequency.to_lowercase();
+        let frequency: u64 = frequency.strip_suffix("hz").ok_or(FlextraceError::BadArgument(filter.to_string()))?.parse()?;
+        // the kernel takes 0 but then never samples anything
+        if frequency == 0 { return Err(FlextraceError::BadArgument(filter.to_string()).into()) }
+        return Ok((event.to_string(), Some(Sampling::Frequency(frequency))))
     }
-    else { event = (filter.to_string(), 0u64); }
 
-    Ok(event)
+    // raw:config can have a :period after it too
+    let (prefix, rest) = match filter.strip_prefix("raw:") {
+        Some(rest) => ("raw:", rest),
+        None => ("", filter),
+    };
+
+    match rest.split_once(":") {
+        Some((event, period)) => {
+            let period: u64 = period.parse()?;
+            if period == 0 { return Err(FlextraceError::BadArgument(filter.to_string()).into()) }
+            Ok((format!("{prefix}{event}"), Some(Sampling::Period(period))))
+        }
+        None => Ok((filter.to_string(), None)),
+    }
 }
 
 // example:
@@ -118,6 +274,60 @@ async fn main() -> anyhow::Result<()> {
         .target(env_logger::Target::Stdout)
         .filter_level(loglevel)
         .init();
+
+    // subcommands that only work on saved files, these don't need the ebpf side at all
+    if let Some(Command::Merge { inputs, out }) = &opt.command {
+        let mut merged = SaveData::new();
+
+        for path in inputs {
+            merged.merge(read_traces_file(path.clone())?)?;
+            debug!("merged profile {path}");
+        }
+
+        save_traces(out.clone(), merged)?;
+        info!("merged {} profiles into {out}", inputs.len());
+        return Ok(())
+    }
+
+    if let Some(Command::Import { input, out }) = &opt.command {
+        let save_data = perf_script::import_perf_script(io::BufReader::new(fs::File::open(input)?))?;
+        info!("imported {} samples from {input}", save_data.samples.len());
+        save_traces(out.clone(), save_data)?;
+        return Ok(())
+    }
+
+    if let Some(Command::Report { input, from, to, format, event, out }) = opt.command.clone() {
+        let mut save_data = read_traces_file(input)?;
+
+        if from.is_some() || to.is_some() {
+            let from_ns = (from.unwrap_or(0.0) * 1e9) as u64;
+            let to_ns = to.map(|to| (to * 1e9) as u64).unwrap_or(u64::MAX - save_data.timeline.start_ns);
+            save_data = save_data.window(from_ns, to_ns);
+        }
+
+        let mut event = PerfEventType::from_str(&event)?;
+        if event == PerfEventType::Any { event = PerfEventType::None; }
+
+        let report: Vec<u8> = match format.as_str() {
+            "pprof" => export::pprof(&save_data.tree, event),
+            "json" => export::json_tree(&save_data.tree).to_string().into_bytes(),
+            "processes" => export::json_profile_data(&save_data).to_string().into_bytes(),
+            "chrome" => export::chrome_trace(&save_data, event).to_string().into_bytes(),
+            "speedscope" => export::speedscope(&save_data).to_string().into_bytes(),
+            "ft" => {
+                let path = out.ok_or(anyhow::Error::msg("ft output needs a path to write to (-o)"))?;
+                save_traces(path, save_data)?;
+                return Ok(())
+            }
+            _ => export::folded(&save_data.tree, event).into_bytes(),
+        };
+
+        match out {
+            Some(path) => std::fs::write(path, report)?,
+            None => io::stdout().write_all(&report)?,
+        }
+        return Ok(())
+    }
     
     // (no need to bump the memlock rlimit cause we don't even support kernels that old)
     //include ebpf program at compile time, load at runtime
@@ -132,10 +342,23 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // apply perf configuration to PERF_CONFIG map
-    if (opt.filter_exclude.get(0).unwrap() != &(0, 0)) || (opt.stack_trace_fp.get(0) != None) {
+    if (opt.filter_exclude.get(0).unwrap() != &(0, EventMask::default())) || (opt.stack_trace_fp.get(0) != None) {
         perf_manager.update_perf_config(&opt.filter_exclude, &opt.stack_trace_fp)?;
     }
 
+    if !opt.allow_pid.is_empty() || !opt.allow_uid.is_empty() || !opt.allow_cgroup.is_empty() || !opt.allow_comm.is_empty() || !opt.container.is_empty() {
+        let mut cgroups = opt.allow_cgroup.iter().map(|path| cgroup_id(path)).collect::<anyhow::Result<Vec<u64>>>()?;
+        for id in &opt.container {
+            let paths = if id.is_empty() { Vec::new() } else { cgroup::find_container(id) };
+            if paths.is_empty() { return Err(FlextraceError::BadArgument(format!("no cgroup for container {id} under /sys/fs/cgroup")).into()) }
+            for path in paths {
+                debug!("container {id} is cgroup {}", path.display());
+                cgroups.push(cgroup_id(&path.to_string_lossy())?);
+            }
+        }
+        perf_manager.update_allowlist(&opt.allow_pid, &opt.allow_uid, &cgroups, &opt.allow_comm)?;
+    }
+
     let mut nextid: u64 = 0;
     let mut event_list: Vec<PerfEventType> = Vec::new();
 
@@ -144,23 +367,27 @@ async fn main() -> anyhow::Result<()> {
     // load and attach perf events, we allow the "all" argument to duplicate events incase people want
     // to do that for some reason and honestly if you pass in an argument like that unintentionally you had it coming lowk
     for event_arg in &opt.events {
-        let period_arg: Option<u64>;
-            match event_arg.1 {
-                0 => period_arg = None,
-                _ => period_arg = Some(event_arg.1),
-            }
+        if event_arg.0.starts_with("tracepoint:") { continue }
+
+        // anything we dont have a name for might still be a raw pmu event
+        let perf_event_enum = match PerfEventType::from_str(&event_arg.0) {
+            Ok(perf_event_enum) => perf_event_enum,
+            Err(e) => match pmu::resolve(&event_arg.0, opt.pmu_events.as_deref())? {
+                Some(config) => perf_manager.raw_event(config, &event_arg.0)?,
+                None => return Err(e.into()),
+            },
+        };
 
-        if !(PerfEventType::from_str(&event_arg.0)? == PerfEventType::Any) {
-            let perf_event_enum = PerfEventType::from_str(&event_arg.0)?;
+        if !(perf_event_enum == PerfEventType::Any) {
 
             if *&opt.processes.len() != 0  {
                 for pid in &opt.processes {
-                    perf_manager.attach_event(perf_event_enum, Some(*pid), period_arg, nextid)?;
+                    perf_manager.attach_event(perf_event_enum, Some(*pid), event_arg.1, nextid)?;
                     nextid += 1;
                 }
             }
             else {
-                perf_manager.attach_event(perf_event_enum, None, period_arg, nextid)?;
+                perf_manager.attach_event(perf_event_enum, None, event_arg.1, nextid)?;
                 nextid += 1;
             }
             event_list.push(perf_event_enum);
@@ -174,15 +401,19 @@ async fn main() -> anyhow::Result<()> {
 
             for name in event_names {
                 let perf_event_enum = PerfEventType::from_str(&name[6..].to_string())?;
-                
+
+                // the cache events would add 42 counters on top that mostly fight over the same few hw
+                // counters, so those have to be asked for by name
+                if perf_event_enum.hw_cache().is_some() { continue }
+
                  if *&opt.processes.len() != 0  {
                     for pid in &opt.processes {
-                        perf_manager.attach_event(perf_event_enum, Some(*pid), period_arg, nextid)?;
+                        perf_manager.attach_event(perf_event_enum, Some(*pid), event_arg.1, nextid)?;
                         nextid += 1;
                     }
                 }
                 else {
-                    perf_manager.attach_event(perf_event_enum, None, period_arg, nextid)?;
+                    perf_manager.attach_event(perf_event_enum, None, event_arg.1, nextid)?;
                     nextid += 1;
                 }
 
@@ -192,6 +423,71 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    for event_arg in &opt.events {
+        let Some(tracepoint) = event_arg.0.strip_prefix("tracepoint:") else { continue };
+        let (category, name) = tracepoint.split_once(":").ok_or(FlextraceError::BadArgument(event_arg.0.clone()))?;
+        event_list.push(perf_manager.attach_tracepoint(category, name, nextid)?);
+        nextid += 1;
+    }
+
+    for uprobe in &opt.uprobe {
+        let (target, symbol) = uprobe.rsplit_once(":").ok_or(FlextraceError::BadArgument(uprobe.clone()))?;
+        event_list.push(perf_manager.attach_uprobe(target, symbol, &opt.processes, nextid)?);
+        nextid += 1;
+    }
+
+    if opt.syscalls {
+        event_list.push(perf_manager.attach_syscalls(nextid)?);
+        nextid += 1;
+    }
+
+    if opt.block_io {
+        event_list.push(perf_manager.attach_block_io(nextid)?);
+        nextid += 1;
+    }
+
+    if opt.locks {
+        event_list.push(perf_manager.attach_locks(nextid)?);
+        nextid += 1;
+    }
+
+    if opt.tcp {
+        event_list.extend(perf_manager.attach_tcp(nextid)?);
+        nextid += 1;
+    }
+
+    if let Some(library) = &opt.alloc {
+        event_list.extend(perf_manager.attach_alloc(library, &opt.processes, nextid)?);
+        nextid += 1;
+    }
+
+    for usdt in &opt.usdt {
+        // probe names are never just a number so a trailing one has to be the arg
+        let (probe, arg) = match usdt.rsplit_once(":") {
+            Some((probe, arg)) if arg.parse::<usize>().is_ok() => (probe, arg.parse::<usize>()?),
+            _ => (usdt.as_str(), 1),
+        };
+        let (rest, name) = probe.rsplit_once(":").ok_or(FlextraceError::BadArgument(usdt.clone()))?;
+        let (target, provider) = rest.rsplit_once(":").ok_or(FlextraceError::BadArgument(usdt.clone()))?;
+        event_list.push(perf_manager.attach_usdt(target, provider, name, arg, &opt.processes, nextid)?);
+        nextid += 1;
+    }
+
+    for function in &opt.kprobe {
+        event_list.push(perf_manager.attach_kprobe(function, nextid)?);
+        nextid += 1;
+    }
+
+    let http_requests = match opt.http {
+        Some(addr) => Some(start_server(addr, opt.http_public).await?),
+        None => None,
+    };
+
+    if let Some(Command::Daemon { dir, interval, max_files, max_bytes, max_age }) = opt.command.clone() {
+        let retention = Retention { max_files, max_bytes, max_age: max_age.map(Duration::from_secs) };
+        return run_daemon(perf_manager, dir, Duration::from_secs(interval), retention, http_requests).await
+    }
+
     enable_raw_mode()?;
     let mut stderr = io::stderr();
     execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
@@ -200,6 +496,9 @@ async fn main() -> anyhow::Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     let mut app: State = State::new(perf_manager, opt.clone(), event_list);
+    app.http_requests = http_requests;
+    app.profile.event_labels = app.perf_manager.event_labels.clone();
+    app.profile.sampling = app.perf_manager.sampling.clone();
 
     run_app(&mut terminal, &mut app).await?;
 
@@ -212,9 +511,28 @@ async fn main() -> anyhow::Result<()> {
     terminal.show_cursor()?;
 
     if let Some(path) = opt.out {
-        let save_data = SaveData {tree: app.tree, data: app.profile_data};
-        save_traces(path, save_data)?;
+        app.profile.counts = app.perf_manager.read_counts();
+        app.perf_manager.record_allocs(&mut app.profile);
+        save_traces(path, app.profile)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sampling() {
+        assert_eq!(parse_events("cycles:1000").unwrap(), ("cycles".to_string(), Some(Sampling::Period(1000))));
+        assert_eq!(parse_events("cycles@99Hz").unwrap(), ("cycles".to_string(), Some(Sampling::Frequency(99))));
+        assert_eq!(parse_events("raw:0x3c:500").unwrap(), ("raw:0x3c".to_string(), Some(Sampling::Period(500))));
+        assert_eq!(parse_events("tracepoint:sched:sched_switch").unwrap(), ("tracepoint:sched:sched_switch".to_string(), None));
+
+        for filter in ["cycles:0", "cycles@0hz", "raw:0x3c:0"] {
+            let error = parse_events(filter).unwrap_err();
+            assert!(matches!(error.downcast_ref::<FlextraceError>(), Some(FlextraceError::BadArgument(bad)) if bad == filter), "{filter}");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use flextrace_common::NsPidOffsets;
+
+use crate::btf::Btf;
+
+// what ebpf needs to read a tasks pid namespace ids on this kernel, see NsPidOffsets
+pub fn ns_pid_offsets(btf: &Btf) -> Option<NsPidOffsets> {
+    Some(NsPidOffsets {
+        task_thread_pid: btf.member_offset("task_struct", "thread_pid")?,
+        task_group_leader: btf.member_offset("task_struct", "group_leader")?,
+        pid_level: btf.member_offset("pid", "level")?,
+        pid_numbers: btf.member_offset("pid", "numbers")?,
+        upid_size: btf.struct_size("upid")?,
+        upid_nr: btf.member_offset("upid", "nr")?,
+    })
+}
+
+// when pid started in clock ticks since boot, field 22 of /proc/pid/stat. together with the pid it
+// tells a process apart from a later one that got the same pid
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // comm is in parens and can have spaces or parens in it, so count from the last one
+    stat[stat.rfind(')')? + 1..].split_whitespace().nth(19)?.parse().ok()
+}
+
+// whether pid sees a different filesystem than we do, which is the case for pretty much every container
+pub fn in_other_mount_ns(pid: u32) -> bool {
+    match (fs::read_link("/proc/self/ns/mnt"), fs::read_link(format!("/proc/{pid}/ns/mnt"))) {
+        (Ok(ours), Ok(theirs)) => ours != theirs,
+        _ => false,
+    }
+}
+
+// where a path inside pids mount namespace can be opened from ours. paths that already go through
+// /proc (like map_files links) work from anywhere
+pub fn host_path(pid: u32, path: &Path) -> PathBuf {
+    if path.starts_with("/proc") { return path.to_path_buf() }
+    Path::new(&format!("/proc/{pid}/root")).join(path.strip_prefix("/").unwrap_or(path))
+}
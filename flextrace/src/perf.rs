@@ -1,24 +1,58 @@
-use std::{collections::HashMap as StdHashMap, num::NonZero};
+use std::{collections::HashMap as StdHashMap, fs, num::NonZero, os::fd::AsFd};
 
 use anyhow::Result;
-use aya::{Ebpf, maps::{MapData, RingBuf, StackTraceMap, stack_trace::{StackTrace}}, programs::{PerfEvent, Program, perf_event::{PerfEventLink, PerfEventScope, SamplePolicy}}, util::online_cpus};
-use blazesym::{Pid, symbolize::{Input, Sym, Symbolized, Symbolizer, source::{Process, Source}}};
+use aya::{Ebpf, maps::{Array, MapData, RingBuf, StackTraceMap, lpm_trie::{Key, LpmTrie}, stack_trace::{StackTrace}}, programs::{KProbe, PerfEvent, Program, TracePoint, UProbe, kprobe::KProbeLink, perf_event::PerfEventConfig, trace_point::TracePointLink, uprobe::UProbeLink}, util::online_cpus};
+use blazesym::{Pid, normalize::{Normalizer, UserMeta}, symbolize::{Input, Sym, Symbolized, Symbolizer, source::{Elf, Kernel, Process, Source}}};
 use aya::maps::HashMap as AyaHashMap;
-use flextrace_common::{FlextraceError, PerfEventType, PerfProcessConfig, PerfSample};
-use log::{debug, error, info};
+use flextrace::{SaveData, timeline::monotonic_ns};
+use flextrace_common::{ALLOW_CGROUPS, ALLOW_COMMS, ALLOW_TGIDS, ALLOW_UIDS, AllocKey, AllocStats, EventCount, EventMask, FlextraceError, NsPidOffsets, PerfEventType, PerfProcessConfig, PerfSample, Sampling, SockOffsets, KPROBE_SLOTS, RAW_SLOTS, TRACEPOINT_SLOTS, UPROBE_SLOTS, USDT_ARG_SPECS, USDT_SLOTS, UsdtArgSpec};
+use log::{debug, error, info, trace, warn};
 use tokio::{io::unix::AsyncFd, sync::mpsc::{self, Receiver}};
 
+use crate::{btf::Btf, counters::{Counter, PERF_TYPE_HARDWARE, PERF_TYPE_HW_CACHE, PERF_TYPE_RAW, PERF_TYPE_SOFTWARE}, namespace, usdt};
+
+// links detach when they get dropped, so all we ever do with these is hold onto them
+#[allow(dead_code)]
+enum AttachedLink {
+    UProbe(UProbeLink),
+    KProbe(KProbeLink),
+    TracePoint(TracePointLink),
+    Usdt(usdt::UsdtProbe),
+}
+
 pub struct PerfManager {
     ebpf: Ebpf,
 
     map_perf_config: AyaHashMap<MapData, u32, PerfProcessConfig>,
     map_stack_traces: StackTraceMap<MapData>,
+    map_usdt_args: Array<MapData, UsdtArgSpec>,
+    map_allow_filter: Array<MapData, u8>,
+    map_allowed_tgids: AyaHashMap<MapData, u32, u8>,
+    map_allowed_uids: AyaHashMap<MapData, u32, u8>,
+    map_allowed_cgroups: AyaHashMap<MapData, u64, u8>,
+    map_allowed_comms: LpmTrie<MapData, [u8; 16], u8>,
+    map_alloc_stats: AyaHashMap<MapData, AllocKey, AllocStats>,
+    map_alloc_skipped: Array<MapData, u64>,
 
     pub event_rx: Receiver<PerfSample>,
     symbolizer: Symbolizer,
+    normalizer: Normalizer,
+    alloc_totals: StdHashMap<AllocKey, AllocStats>, // ALLOC_STATS as of the last read_allocs
+    alloc_skipped: u64, // ALLOC_SKIPPED as of the last read_allocs
+    other_mount_ns: StdHashMap<(u32, u64), bool>, // per pid and start time, whether its binaries have to be found through /proc/pid/root
 
-    links: StdHashMap<u64, Vec<PerfEventLink>>,
+    links: StdHashMap<u64, Vec<AttachedLink>>,
+    counters: StdHashMap<u64, (PerfEventType, Vec<(Option<u32>, Counter)>)>, // the attached perf events by id like links, with the thread they sample
     pub event_list: Vec<String>,
+    pub sampling: StdHashMap<PerfEventType, Sampling>,
+    pub event_labels: StdHashMap<PerfEventType, String>,
+    raw_configs: StdHashMap<PerfEventType, u64>,
+    next_uprobe_slot: u8,
+    next_kprobe_slot: u8,
+    next_tracepoint_slot: u8,
+    next_usdt_slot: u8,
+    next_usdt_arg_spec: u32,
+    next_raw_slot: u8,
 }
 
 impl PerfManager {
@@ -46,7 +80,22 @@ impl PerfManager {
                 Program::PerfEvent(p) => {
                     p.load()?;
                     debug!("loaded event {name}");
-                    prog_names.push(name.to_string());
+                    // the raw_ ones are only there for slots
+                    if name.starts_with("event_") {
+                        prog_names.push(name.to_string());
+                    }
+                },
+                Program::UProbe(p) => {
+                    p.load()?;
+                    debug!("loaded uprobe {name}");
+                },
+                Program::KProbe(p) => {
+                    p.load()?;
+                    debug!("loaded kprobe {name}");
+                },
+                Program::TracePoint(p) => {
+                    p.load()?;
+                    debug!("loaded tracepoint {name}");
                 },
                 _ => continue,
             }
@@ -66,6 +115,32 @@ impl PerfManager {
             let raw_map = ebpf.take_map("PERF_STACK_TRACES").unwrap();
             StackTraceMap::try_from(raw_map).unwrap()
         };
+        let usdt_args = {
+            let raw_map = ebpf.take_map("USDT_ARGS").unwrap();
+            Array::try_from(raw_map).unwrap()
+        };
+        let allow_filter = Array::try_from(ebpf.take_map("ALLOW_FILTER").unwrap()).unwrap();
+        let allowed_tgids = AyaHashMap::try_from(ebpf.take_map("ALLOWED_TGIDS").unwrap()).unwrap();
+        let allowed_uids = AyaHashMap::try_from(ebpf.take_map("ALLOWED_UIDS").unwrap()).unwrap();
+        let allowed_cgroups = AyaHashMap::try_from(ebpf.take_map("ALLOWED_CGROUPS").unwrap()).unwrap();
+        let allowed_comms = LpmTrie::try_from(ebpf.take_map("ALLOWED_COMMS").unwrap()).unwrap();
+        let alloc_stats = AyaHashMap::try_from(ebpf.take_map("ALLOC_STATS").unwrap()).unwrap();
+        let alloc_skipped = Array::try_from(ebpf.take_map("ALLOC_SKIPPED").unwrap()).unwrap();
+
+        // samples just dont get namespace ids without these
+        let btf = Btf::from_sys();
+        let mut ns_pid_offsets: Array<MapData, NsPidOffsets> = Array::try_from(ebpf.take_map("NS_PID_OFFSETS").unwrap()).unwrap();
+        match btf.as_ref().and_then(namespace::ns_pid_offsets) {
+            Some(offsets) => ns_pid_offsets.set(0, offsets, 0)?,
+            None => warn!("could not find where pid namespace ids are in this kernels BTF, pids wont show as containers see them"),
+        }
+
+        // and tcp events dont get anything at all
+        let mut sock_offsets_map: Array<MapData, SockOffsets> = Array::try_from(ebpf.take_map("SOCK_OFFSETS").unwrap()).unwrap();
+        match btf.as_ref().and_then(sock_offsets) {
+            Some(offsets) => sock_offsets_map.set(0, offsets, 0)?,
+            None => warn!("could not find where sockets keep their addresses in this kernels BTF, there wont be any tcp events"),
+        }
         debug!("maps initialized");
 
         let mut ringbuf_fd = AsyncFd::new(event_map)?;
@@ -87,15 +162,37 @@ impl PerfManager {
             ebpf: ebpf,
             map_perf_config: config_map,
             map_stack_traces: stack_traces,
+            map_usdt_args: usdt_args,
+            map_allow_filter: allow_filter,
+            map_allowed_tgids: allowed_tgids,
+            map_allowed_uids: allowed_uids,
+            map_allowed_cgroups: allowed_cgroups,
+            map_allowed_comms: allowed_comms,
+            map_alloc_stats: alloc_stats,
+            map_alloc_skipped: alloc_skipped,
+            alloc_totals: StdHashMap::new(),
+            alloc_skipped: 0,
             links: StdHashMap::new(),
+            counters: StdHashMap::new(),
             event_rx: perf_rx,
             symbolizer: Symbolizer::new(),
+            normalizer: Normalizer::new(),
+            other_mount_ns: StdHashMap::new(),
             event_list: prog_names,
+            sampling: StdHashMap::new(),
+            event_labels: StdHashMap::new(),
+            raw_configs: StdHashMap::new(),
+            next_uprobe_slot: 0,
+            next_kprobe_slot: 0,
+            next_tracepoint_slot: 0,
+            next_usdt_slot: 0,
+            next_usdt_arg_spec: 0,
+            next_raw_slot: 0,
         })
     }
 
-    pub fn attach_event(&mut self, perf_event_enum: PerfEventType, pid: Option<u32>, period: Option<u64>, id: u64) -> anyhow::Result<()> {
-        let perf_config = perf_event_enum.perf_config()?;
+    pub fn attach_event(&mut self, perf_event_enum: PerfEventType, pid: Option<u32>, sampling: Option<Sampling>, id: u64) -> anyhow::Result<()> {
+        let (event_type, config) = self.event_config(perf_event_enum)?;
 
         let perf_ebpf_name = match perf_event_enum.ebpf_from_self() {
             Some(name) => name,
@@ -106,38 +203,378 @@ impl PerfManager {
             .ok_or(FlextraceError::NoSuchProgram(String::from(&perf_ebpf_name)))?
             .try_into()
             .map_err(|_| FlextraceError::Msg(String::from("failed to convert aya Program to PerfEvent? tell me about this bug")))?;
+        let program = perf_event.fd()?.as_fd();
+
+        let sampling = sampling.unwrap_or(perf_event_enum.default_sampling());
+
+        // we open the events ourselves instead of through aya so the same event that samples can be read
+        // for its total, see read_counts. one per thread of pid so every thread has its own total, or one
+        // per cpu for everything
+        let scopes: Vec<(Option<u32>, Option<u32>)> = match pid {
+            Some(pid) => fs::read_dir(format!("/proc/{pid}/task"))
+                .map(|tasks| tasks.filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok()).collect())
+                .unwrap_or(vec![pid])
+                .into_iter().map(|tid| (Some(tid), None)).collect(),
+            None => online_cpus().map_err(|(_, error)| error)?.into_iter().map(|cpu| (None, Some(cpu))).collect(),
+        };
 
-        let mut links: Vec<PerfEventLink> = Vec::new();
+        let mut counters: Vec<(Option<u32>, Counter)> = Vec::new();
+        for (tid, cpu) in scopes {
+            match Counter::open(event_type, config, sampling, tid, cpu, program) {
+                Ok(counter) => counters.push((tid, counter)),
+                Err(e) => {
+                    info!("system does not support perf event {perf_ebpf_name}: {e}");
+                    break;
+                },
+            };
+        }
+        let scope_info = pid.map(|pid| format!("pid {pid}")).unwrap_or(String::from("all processes"));
+        debug!("attached perf event {perf_ebpf_name} with id: {id} sampling: {sampling} scope: {scope_info} ({} events)", counters.len());
 
-        let mut some_period: u64 = 100000;
+        self.counters.insert(id, (perf_event_enum, counters));
+        self.sampling.insert(perf_event_enum, sampling);
+        Ok(())
+    }
 
-        if let Some(period) = period {
-            some_period = period;
+    // (perf type, config) to open event with
+    fn event_config(&self, event: PerfEventType) -> Result<(u32, u64)> {
+        if let Some(config) = self.raw_configs.get(&event) {
+            return Ok((PERF_TYPE_RAW, *config))
         }
+        // our cache, op and result indices are the kernels ids too
+        if let Some((cache, op, result)) = event.hw_cache() {
+            return Ok((PERF_TYPE_HW_CACHE, cache as u64 | (op as u64) << 8 | (result as u64) << 16))
+        }
+        match event.perf_config()? {
+            PerfEventConfig::Hardware(hardware) => Ok((PERF_TYPE_HARDWARE, hardware as u64)),
+            PerfEventConfig::Software(software) => Ok((PERF_TYPE_SOFTWARE, software as u64)),
+            _ => Err(FlextraceError::NoPerfSwId(event.ebpf_from_self().unwrap_or_default()).into()),
+        }
+    }
 
-        let mut scope_info = String::from("all processes");
+    // totals of every attached perf event so far, by event and thread (None for system wide). each event
+    // gets scaled up by enabled/running for the time it was multiplexed off the pmu
+    pub fn read_counts(&self) -> StdHashMap<(PerfEventType, Option<u32>), EventCount> {
+        let mut counts: StdHashMap<(PerfEventType, Option<u32>), EventCount> = StdHashMap::new();
+
+        for (event, counters) in self.counters.values() {
+            for (tid, counter) in counters {
+                let count = counts.entry((*event, *tid)).or_default();
+                let Ok((value, enabled, running)) = counter.read() else { continue };
+                if running == 0 { continue }
+                count.value += (value as u128 * enabled as u128 / running as u128) as u64;
+                count.enabled_ns = count.enabled_ns.max(enabled);
+            }
+        }
 
-        for cpu in online_cpus().map_err(|(_, error)| error)? {
-            match perf_event.attach(
-                perf_config,
-                match pid {
-                    Some(some_pid) => {
-                        scope_info = String::from("pid ") + &some_pid.to_string();
-                        PerfEventScope::OneProcess { pid: some_pid, cpu: Some(cpu) }
-                },
-                    None => PerfEventScope::AllProcessesOneCpu { cpu },
-                },
-                SamplePolicy::Period(some_period),
-                true,
-            ) {
-                Ok(link_id) => links.push(perf_event.take_link(link_id)?),
-                Err(_e) => {
-                    info!("system does not support perf event {}", perf_event_enum.ebpf_from_self().unwrap_or(String::from("could not get the string version of this event i guess, weird...")));
-                    break;
-                },
+        counts
+    }
+
+    // adds whatever got allocated and freed since the last call to profile, allocations whose stack we
+    // cant get stay at the root
+    pub fn record_allocs(&mut self, profile: &mut SaveData) {
+        for (sample, allocs) in self.read_allocs() {
+            let trace = self.trace_for_sample(&sample).unwrap_or_else(|e| {
+                debug!("failed to get the stack of some allocations, keeping them without one: {e}");
+                None
+            });
+            profile.record_allocs(&sample, &allocs, trace);
+        }
+    }
+
+    // the difference in ALLOC_STATS since the last call, per thread and allocating stack. the sample has
+    // the thread and stack like one out of the ring buffer would
+    fn read_allocs(&mut self) -> Vec<(PerfSample, AllocStats)> {
+        let now = monotonic_ns();
+        let mut allocs: Vec<(PerfSample, AllocStats)> = Vec::new();
+
+        // blocks that came in while there were too many live ones to keep track of
+        let skipped = self.map_alloc_skipped.get(&0, 0).unwrap_or(0);
+        if skipped > self.alloc_skipped {
+            if self.alloc_skipped == 0 { warn!("too many live allocations to keep track of, new ones arent being counted until some get freed") }
+            debug!("{skipped} allocations skipped so far");
+            self.alloc_skipped = skipped;
+        }
+
+        for entry in self.map_alloc_stats.iter() {
+            let Ok((key, totals)) = entry.map_err(|e| debug!("could not read ALLOC_STATS: {e}")) else { continue };
+            let seen = self.alloc_totals.insert(key, totals).unwrap_or_default();
+            if totals.allocs == seen.allocs && totals.frees == seen.frees { continue }
+
+            let since = AllocStats {
+                allocs: totals.allocs.wrapping_sub(seen.allocs),
+                bytes: totals.bytes.wrapping_sub(seen.bytes),
+                frees: totals.frees.wrapping_sub(seen.frees),
+                freed_bytes: totals.freed_bytes.wrapping_sub(seen.freed_bytes),
+                sizes: std::array::from_fn(|bucket| totals.sizes[bucket].wrapping_sub(seen.sizes[bucket])),
+                ..totals
+            };
+            let sample = PerfSample {
+                event_type: PerfEventType::Alloc,
+                pid: key.pid,
+                tgid: key.tgid,
+                uid: totals.uid,
+                gid: totals.gid,
+                cmd: totals.cmd,
+                stack_id: Some(key.stack_id),
+                timestamp: now,
+                value: since.bytes,
+                cgroup: totals.cgroup,
+                ns_pid: totals.ns_pid,
+                ns_tgid: totals.ns_tgid,
+                ..Default::default()
             };
+            allocs.push((sample, since));
+        }
+
+        allocs
+    }
+
+    // gives a raw pmu config (see pmu::resolve) the next free raw slot, the returned event then gets
+    // attached like any other with attach_event
+    pub fn raw_event(&mut self, config: u64, label: &str) -> Result<PerfEventType> {
+        let slot = self.next_raw_slot;
+        let event = PerfEventType::raw(slot).ok_or(FlextraceError::TooManyEvents(format!("only {RAW_SLOTS} raw events can be attached at once")))?;
+        debug!("raw event {label} is config {config:#x} in raw slot {slot}");
+
+        self.next_raw_slot += 1;
+        self.raw_configs.insert(event, config);
+        self.event_labels.insert(event, label.to_string());
+        Ok(event)
+    }
+
+    // hooks up the next free uprobe slot to the entry and return of symbol in target (a binary or
+    // shared library), every call then shows up as a sample of the returned event with its latency as the value.
+    // no pids means every process that maps target
+    pub fn attach_uprobe(&mut self, target: &str, symbol: &str, pids: &Vec<u32>, id: u64) -> Result<PerfEventType> {
+        let slot = self.next_uprobe_slot;
+        let event = PerfEventType::uprobe(slot).ok_or(FlextraceError::TooManyEvents(format!("only {UPROBE_SLOTS} uprobes can be attached at once")))?;
+
+        let mut links: Vec<AttachedLink> = Vec::new();
+        let scopes = uprobe_scopes(pids)?;
+
+        // every slot shares the same two programs, the cookie is how they know which one fired
+        for prog_name in ["uprobe_entry", "uprobe_return"] {
+            self.attach_uprobe_program(prog_name, target, symbol, &scopes, Some(slot as u64), &mut links)?;
+        }
+        debug!("attached {target}:{symbol} to uprobe slot {slot} with id: {id}");
+
+        self.next_uprobe_slot += 1;
+        self.links.insert(id, links);
+        self.event_labels.insert(event, format!("uprobe:{target}:{symbol}"));
+        Ok(event)
+    }
+
+    // uprobes on the allocation functions in library (libc unless youre using some other allocator), the tree
+    // then gets allocated bytes and live bytes (allocated minus freed) per stack. mmap and munmap are
+    // optional since allocator libraries usually leave those to libc
+    pub fn attach_alloc(&mut self, library: &str, pids: &Vec<u32>, id: u64) -> Result<Vec<PerfEventType>> {
+        let mut links: Vec<AttachedLink> = Vec::new();
+        let scopes = uprobe_scopes(pids)?;
+
+        let required = [
+            ("alloc_malloc", "malloc"), ("alloc_heap_return", "malloc"),
+            ("alloc_calloc", "calloc"), ("alloc_heap_return", "calloc"),
+            ("alloc_realloc", "realloc"), ("alloc_heap_return", "realloc"),
+            ("alloc_free", "free"),
+        ];
+        let optional = [("alloc_mmap", "mmap"), ("alloc_mmap_return", "mmap"), ("alloc_free", "munmap")];
+
+        for (prog_name, symbol) in required {
+            self.attach_uprobe_program(prog_name, library, symbol, &scopes, None, &mut links)?;
+        }
+        for (prog_name, symbol) in optional {
+            if let Err(e) = self.attach_uprobe_program(prog_name, library, symbol, &scopes, None, &mut links) {
+                warn!("not tracking {symbol} in {library}: {e}");
+            }
+        }
+        debug!("attached allocation uprobes in {library} with id: {id}");
+
+        self.links.insert(id, links);
+        Ok(vec![PerfEventType::Alloc, PerfEventType::LiveBytes, PerfEventType::Free])
+    }
+
+    fn attach_uprobe_program(&mut self, prog_name: &str, target: &str, symbol: &str, scopes: &Vec<Option<i32>>, cookie: Option<u64>, links: &mut Vec<AttachedLink>) -> Result<()> {
+        let uprobe: &mut UProbe = self.ebpf.program_mut(prog_name)
+            .ok_or(FlextraceError::NoSuchProgram(prog_name.to_string()))?
+            .try_into()
+            .map_err(|_| FlextraceError::Msg(String::from("failed to convert aya Program to UProbe? tell me about this bug")))?;
+
+        for pid in scopes {
+            let link_id = uprobe.attach(symbol, target, *pid, cookie)
+                .map_err(|e| FlextraceError::UProbeAttachFailure(format!("{target}:{symbol} ({e})")))?;
+            links.push(AttachedLink::UProbe(uprobe.take_link(link_id)?));
+        }
+
+        Ok(())
+    }
+
+    // same idea as attach_uprobe but for a kernel function, kprobes fire for every process so the pid
+    // filtering happens through PERF_CONFIG like it does for everything else
+    pub fn attach_kprobe(&mut self, function: &str, id: u64) -> Result<PerfEventType> {
+        let slot = self.next_kprobe_slot;
+        let event = PerfEventType::kprobe(slot).ok_or(FlextraceError::TooManyEvents(format!("only {KPROBE_SLOTS} kprobes can be attached at once")))?;
+
+        let mut links: Vec<AttachedLink> = Vec::new();
+
+        for prog_name in [format!("kprobe_{slot}"), format!("kretprobe_{slot}")] {
+            let kprobe: &mut KProbe = self.ebpf.program_mut(&prog_name)
+                .ok_or(FlextraceError::NoSuchProgram(prog_name.clone()))?
+                .try_into()
+                .map_err(|_| FlextraceError::Msg(String::from("failed to convert aya Program to KProbe? tell me about this bug")))?;
+
+            let link_id = kprobe.attach(function, 0)
+                .map_err(|e| FlextraceError::KProbeAttachFailure(format!("{function} ({e})")))?;
+            links.push(AttachedLink::KProbe(kprobe.take_link(link_id)?));
+        }
+        debug!("attached {function} to kprobe slot {slot} with id: {id}");
+
+        self.next_kprobe_slot += 1;
+        self.links.insert(id, links);
+        self.event_labels.insert(event, format!("kprobe:{function}"));
+        Ok(event)
+    }
+
+    // every hit of category:name (as in /sys/kernel/tracing/events/category/name) becomes a sample
+    // of the returned event, same as a perf event with a period of 1
+    pub fn attach_tracepoint(&mut self, category: &str, name: &str, id: u64) -> Result<PerfEventType> {
+        let slot = self.next_tracepoint_slot;
+        let event = PerfEventType::tracepoint(slot).ok_or(FlextraceError::TooManyEvents(format!("only {TRACEPOINT_SLOTS} tracepoints can be attached at once")))?;
+
+        let prog_name = format!("tracepoint_{slot}");
+        let tracepoint: &mut TracePoint = self.ebpf.program_mut(&prog_name)
+            .ok_or(FlextraceError::NoSuchProgram(prog_name.clone()))?
+            .try_into()
+            .map_err(|_| FlextraceError::Msg(String::from("failed to convert aya Program to TracePoint? tell me about this bug")))?;
+
+        let link_id = tracepoint.attach(category, name)
+            .map_err(|e| FlextraceError::TracepointAttachFailure(format!("{category}:{name} ({e})")))?;
+        let links = vec![AttachedLink::TracePoint(tracepoint.take_link(link_id)?)];
+        debug!("attached {category}:{name} to tracepoint slot {slot} with id: {id}");
+
+        self.next_tracepoint_slot += 1;
+        self.links.insert(id, links);
+        self.sampling.insert(event, Sampling::Period(1));
+        self.event_labels.insert(event, format!("tracepoint:{category}:{name}"));
+        Ok(event)
+    }
+
+    // attaches to every site of provider:name in target's .note.stapsdt section, each hit is a sample of the
+    // returned event with the probes arg'th argument (counting from 1, 0 for none) as its value. the
+    // kernel takes care of semaphores so probes that have one fire in every process we attach to
+    pub fn attach_usdt(&mut self, target: &str, provider: &str, name: &str, arg: usize, pids: &Vec<u32>, id: u64) -> Result<PerfEventType> {
+        let slot = self.next_usdt_slot;
+        let event = PerfEventType::usdt(slot).ok_or(FlextraceError::TooManyEvents(format!("only {USDT_SLOTS} usdt probes can be attached at once")))?;
+
+        let notes: Vec<usdt::UsdtNote> = usdt::read_notes(target)?.into_iter().filter(|note| note.provider == provider && note.name == name).collect();
+        if notes.len() == 0 {
+            return Err(FlextraceError::UsdtAttachFailure(format!("no usdt probe {provider}:{name} in {target}")).into())
+        }
+        if self.next_usdt_arg_spec + notes.len() as u32 > USDT_ARG_SPECS {
+            return Err(FlextraceError::TooManyEvents(format!("only {USDT_ARG_SPECS} usdt probe sites can be attached at once")).into())
+        }
+
+        // sites that dont have the arg at all just read as 0, but one we cant fetch is an error rather than a
+        // quiet 0 since the values would be garbage
+        let mut specs: Vec<UsdtArgSpec> = Vec::new();
+        for note in &notes {
+            specs.push(match arg.checked_sub(1).and_then(|index| note.args.get(index)) {
+                Some((spec, None)) => return Err(FlextraceError::UsdtAttachFailure(format!(
+                    "cant read argument {arg} ({spec}) of {provider}:{name} at {:#x}, pass :0 to only count hits", note.offset)).into()),
+                Some((_, Some(parsed))) => *parsed,
+                None => UsdtArgSpec::default(),
+            });
+        }
+
+        let mut links: Vec<AttachedLink> = Vec::new();
+
+        let scopes = uprobe_scopes(pids)?;
+        let prog_name = format!("usdt_{slot}");
+        let uprobe: &mut UProbe = self.ebpf.program_mut(&prog_name)
+            .ok_or(FlextraceError::NoSuchProgram(prog_name.clone()))?
+            .try_into()
+            .map_err(|_| FlextraceError::Msg(String::from("failed to convert aya Program to UProbe? tell me about this bug")))?;
+        let program = uprobe.fd()?.as_fd();
+
+        for (note, spec) in notes.iter().zip(specs) {
+            // the cookie tells the ebpf side which arg spec belongs to the site that fired
+            let spec_index = self.next_usdt_arg_spec;
+            self.map_usdt_args.set(spec_index, spec, 0)?;
+            self.next_usdt_arg_spec += 1;
+
+            for pid in &scopes {
+                let probe = usdt::UsdtProbe::attach(program, target, note, *pid, spec_index as u64)
+                    .map_err(|e| FlextraceError::UsdtAttachFailure(format!("{target}:{provider}:{name} ({e})")))?;
+                links.push(AttachedLink::Usdt(probe));
+            }
         }
-        debug!("attached perf event {perf_ebpf_name} with id: {id} sampling period: {some_period} scope: {scope_info}");
+        debug!("attached {target}:{provider}:{name} ({} sites) to usdt slot {slot} with id: {id}", notes.len());
+
+        self.next_usdt_slot += 1;
+        self.links.insert(id, links);
+        self.event_labels.insert(event, format!("usdt:{provider}:{name}"));
+        Ok(event)
+    }
+
+    // times every syscall from enter to exit, samples are keyed by syscall number and count as failed if it returned an error
+    pub fn attach_syscalls(&mut self, id: u64) -> Result<PerfEventType> {
+        self.attach_named_tracepoints("raw_syscalls", &["sys_enter", "sys_exit"], id)?;
+        Ok(PerfEventType::Syscall)
+    }
+
+    // times every block request from issue to completion, keyed by device and attributed to whoever issued it
+    pub fn attach_block_io(&mut self, id: u64) -> Result<PerfEventType> {
+        self.attach_named_tracepoints("block", &["block_bio_queue", "block_rq_issue", "block_rq_complete"], id)?;
+        Ok(PerfEventType::BlockIo)
+    }
+
+    // times every futex wait (which is what contended pthread mutexes, condvars and most other locks end up
+    // in), weighted by how long it blocked and keyed by the futex address
+    pub fn attach_locks(&mut self, id: u64) -> Result<PerfEventType> {
+        self.attach_named_tracepoints("syscalls", &["sys_enter_futex", "sys_exit_futex"], id)?;
+        Ok(PerfEventType::LockWait)
+    }
+
+    // bytes sent and received and retransmits per connection, attributed to the process (and stack) doing
+    // the sending or receiving
+    pub fn attach_tcp(&mut self, id: u64) -> Result<Vec<PerfEventType>> {
+        let mut links: Vec<AttachedLink> = Vec::new();
+
+        let programs = [
+            ("tcp_sendmsg", "tcp_sendmsg"), ("tcp_sendmsg_return", "tcp_sendmsg"),
+            ("tcp_cleanup_rbuf", "tcp_cleanup_rbuf"), ("tcp_retransmit_skb", "tcp_retransmit_skb"), ("tcp_close", "tcp_close"),
+        ];
+        for (program, function) in programs {
+            let kprobe: &mut KProbe = self.ebpf.program_mut(program)
+                .ok_or(FlextraceError::NoSuchProgram(program.to_string()))?
+                .try_into()
+                .map_err(|_| FlextraceError::Msg(String::from("failed to convert aya Program to KProbe? tell me about this bug")))?;
+
+            let link_id = kprobe.attach(function, 0)
+                .map_err(|e| FlextraceError::KProbeAttachFailure(format!("{function} ({e})")))?;
+            links.push(AttachedLink::KProbe(kprobe.take_link(link_id)?));
+        }
+        debug!("attached tcp kprobes with id: {id}");
+
+        self.links.insert(id, links);
+        Ok(vec![PerfEventType::TcpSend, PerfEventType::TcpRecv, PerfEventType::TcpRetransmit])
+    }
+
+    // for the fixed programs that are named after the tracepoint they go on
+    fn attach_named_tracepoints(&mut self, category: &str, prog_names: &[&str], id: u64) -> Result<()> {
+        let mut links: Vec<AttachedLink> = Vec::new();
+
+        for prog_name in prog_names {
+            let tracepoint: &mut TracePoint = self.ebpf.program_mut(prog_name)
+                .ok_or(FlextraceError::NoSuchProgram(prog_name.to_string()))?
+                .try_into()
+                .map_err(|_| FlextraceError::Msg(String::from("failed to convert aya Program to TracePoint? tell me about this bug")))?;
+
+            let link_id = tracepoint.attach(category, prog_name)
+                .map_err(|e| FlextraceError::TracepointAttachFailure(format!("{category}:{prog_name} ({e})")))?;
+            links.push(AttachedLink::TracePoint(tracepoint.take_link(link_id)?));
+        }
+        debug!("attached {category} tracepoints with id: {id}");
 
         self.links.insert(id, links);
         Ok(())
@@ -145,10 +582,11 @@ impl PerfManager {
 
     pub fn detach_event(&mut self, id: u64) {
         self.links.remove(&id);
+        self.counters.remove(&id);
         debug!("detached perf event with id {id}");
     }
 
-    pub fn update_perf_config(&mut self, filter_exclude: &Vec<(u32, u32)>, stack_trace_fp: &Vec<u32>) -> Result<()> {
+    pub fn update_perf_config(&mut self, filter_exclude: &Vec<(u32, EventMask)>, stack_trace_fp: &Vec<u32>) -> Result<()> {
         let mut config_temp: StdHashMap<u32, PerfProcessConfig> = StdHashMap::new();
 
         for (key, mask) in filter_exclude {
@@ -156,51 +594,179 @@ impl PerfManager {
         }
 
         for key in stack_trace_fp {
-            config_temp.entry(*key).and_modify(|config| config.1 = true).or_insert(PerfProcessConfig(0, true));
+            config_temp.entry(*key).and_modify(|config| config.1 = true).or_insert(PerfProcessConfig(EventMask::default(), true));
         }
 
         for (key, config) in config_temp {
-            debug!("config for pid {key}: fp stack traces: {}, mask: {}", config.1, config.0);
+            debug!("config for pid {key}: fp stack traces: {}, mask: {:?}", config.1, config.0);
             self.map_perf_config.insert(key, config, 0)?;
         }
 
         Ok(())
     }
 
+    // only lets samples from tasks on every non empty list through, checked in the ebpf side before anything
+    // gets put on the ring buffer. comms are prefixes, and only the first 15 bytes of a comm exist anyway
+    pub fn update_allowlist(&mut self, tgids: &Vec<u32>, uids: &Vec<u32>, cgroups: &Vec<u64>, comms: &Vec<String>) -> Result<()> {
+        let mut filter: u8 = 0;
+
+        for tgid in tgids {
+            self.map_allowed_tgids.insert(*tgid, 1, 0)?;
+            filter |= ALLOW_TGIDS;
+        }
+        for uid in uids {
+            self.map_allowed_uids.insert(*uid, 1, 0)?;
+            filter |= ALLOW_UIDS;
+        }
+        for cgroup in cgroups {
+            self.map_allowed_cgroups.insert(*cgroup, 1, 0)?;
+            filter |= ALLOW_CGROUPS;
+        }
+        for comm in comms {
+            let prefix = &comm.as_bytes()[..comm.len().min(15)];
+            let mut data = [0u8; 16];
+            data[..prefix.len()].copy_from_slice(prefix);
+            self.map_allowed_comms.insert(&Key::new(prefix.len() as u32 * 8, data), 1, 0)?;
+            filter |= ALLOW_COMMS;
+        }
+
+        debug!("allowlist filter {filter:#b}: tgids {tgids:?} uids {uids:?} cgroups {cgroups:?} comms {comms:?}");
+        self.map_allow_filter.set(0, filter, 0)?;
+        Ok(())
+    }
+
+    // turns the stack ids attached to a sample into a symbolized trace, if there is one. kernel
+    // frames are innermost so they go in front of the user ones
+    pub fn trace_for_sample(&mut self, sample: &PerfSample) -> Result<Option<Vec<String>>> {
+        let mut trace_parsed: Vec<String> = Vec::new();
+
+        if let Some(stackid) = valid_stack_id(sample.kernel_stack_id) {
+            let trace = self.get_stack_fp(stackid)?;
+            trace!("generated kernel stack trace from stackid {stackid}");
+            trace_parsed.extend(self.symbolize_trace(trace, Source::Kernel(Kernel::default()))?);
+        }
+
+        if let Some(stackid) = valid_stack_id(sample.stack_id) {
+            let trace = self.get_stack_fp(stackid)?;
+            trace!("generated stack trace from stackid {stackid}");
+            trace_parsed.extend(self.symbolize_fp_trace(trace, sample.pid)?);
+        }
+
+        if trace_parsed.len() == 0 { return Ok(None) }
+        Ok(Some(trace_parsed))
+    }
+
     pub fn get_stack_fp(&mut self, id: i64) -> Result<StackTrace, aya::maps::MapError> {
         self.map_stack_traces.get(&(id as u32), 0)
     }
 
     pub fn symbolize_fp_trace(&mut self, trace: StackTrace, pid: u32) -> Result<Vec<String>> {
+        let Some(nonzero_pid) = NonZero::new(pid) else { return Ok(Vec::new()) };
+
+        // a process thats already gone cant be looked into anyway
+        let in_container = match namespace::start_time(pid) {
+            Some(start) => *self.other_mount_ns.entry((pid, start)).or_insert_with(|| namespace::in_other_mount_ns(pid)),
+            None => false,
+        };
+        if in_container {
+            let ips: Vec<u64> = trace.frames().iter().map(|frame| frame.ip).collect();
+            match self.symbolize_in_root(&ips, nonzero_pid) {
+                Ok(trace_parsed) => return Ok(trace_parsed),
+                Err(e) => debug!("could not symbolize pid {pid} through its root, trying it from here: {e}"),
+            }
+        }
+        self.symbolize_trace(trace, Source::Process(Process::new(Pid::Pid(nonzero_pid))))
+    }
+
+    fn symbolize_trace(&mut self, trace: StackTrace, source: Source) -> Result<Vec<String>> {
         let mut ips: Vec<u64> = Vec::new();
 
         for frame in trace.frames() {
             ips.push(frame.ip);
         }
 
-        let syms = self.symbolizer.symbolize(&Source::Process(Process::new(Pid::Pid(NonZero::new(pid).unwrap()))), Input::AbsAddr(&ips))?;
+        let syms = self.symbolizer.symbolize(&source, Input::AbsAddr(&ips))?;
+        let mut trace_parsed: Vec<String> = syms.into_iter().map(|sym| frame_name(sym, None)).collect();
+
+        for i in 0..trace_parsed.len() {
+            trace_parsed[i].push_str(&ips[i].to_string());
+        }
+
+        Ok(trace_parsed)
+    }
+
+    // binaries inside a container are at some path only its mount namespace has, if the host has that
+    // path at all. so the addresses get turned into file offsets in whatever is mapped there, and those
+    // files get opened through /proc/pid/root. modules keep the path the container sees, so the same
+    // binary comes out with the same name no matter which pid ran it
+    fn symbolize_in_root(&mut self, ips: &[u64], pid: NonZero<u32>) -> Result<Vec<String>> {
+        let normalized = self.normalizer.normalize_user_addrs(Pid::Pid(pid), ips)?;
         let mut trace_parsed: Vec<String> = Vec::new();
 
-        for result in syms {
-            match result {
-                Symbolized::Sym(Sym {
-                    name,
-                    module,
-                    ..
-                }) => {
-                    // im sorry about this
-                    let namestr: String = module.unwrap().to_str().unwrap().to_string() + ":" + &name.to_string() + "(at)";
-                    trace_parsed.push(namestr);
+        for (i, (offset, meta_index)) in normalized.outputs.iter().enumerate() {
+            let mut namestr = match &normalized.meta[*meta_index] {
+                UserMeta::Elf(elf) => {
+                    let source = Source::Elf(Elf::new(namespace::host_path(pid.get(), &elf.path)));
+                    frame_name(self.symbolizer.symbolize_single(&source, Input::FileOffset(*offset))?, Some(&elf.path.to_string_lossy()))
                 }
-                Symbolized::Unknown(..) =>  { trace_parsed.push(String::from("nosym:")) }
-            }
+                // vdso, anonymous memory and such
+                _ => String::from("nosym:"),
+            };
+            namestr.push_str(&ips[i].to_string());
+            trace_parsed.push(namestr);
         }
 
-        for i in 0..trace_parsed.len() {
-            trace_parsed[i].push_str(&ips[i].to_string());
+        Ok(trace_parsed)
+    }
+}
+
+// module:symbol(at) for the ip to go after, or nosym: if there wasnt one. module_name replaces whatever
+// module the symbolizer says the symbol is from
+fn frame_name(symbolized: Symbolized, module_name: Option<&str>) -> String {
+    match symbolized {
+        Symbolized::Sym(Sym {
+            name,
+            module,
+            ..
+        }) => {
+            // kernel symbols dont come with a module
+            let module = match module_name {
+                Some(module_name) => module_name.to_string(),
+                None => module.map(|module| module.to_string_lossy().to_string()).unwrap_or(String::from("kernel")),
+            };
+            module + ":" + &name.to_string() + "(at)"
         }
+        Symbolized::Unknown(..) => String::from("nosym:"),
+    }
+}
 
-        Ok(trace_parsed)
+// which processes to attach a uprobe in, aya takes them as pid_t and None is every process
+fn uprobe_scopes(pids: &Vec<u32>) -> Result<Vec<Option<i32>>> {
+    if pids.is_empty() { return Ok(vec![None]) }
+    pids.iter().map(|pid| Ok(Some(i32::try_from(*pid).map_err(|_| FlextraceError::BadArgument(format!("pid {pid} is out of range")))?))).collect()
+}
+
+// what ebpf needs to read a connections addresses and ports on this kernel, see SockOffsets
+fn sock_offsets(btf: &Btf) -> Option<SockOffsets> {
+    let member = |name: &str| btf.member_offset("sock_common", name);
+    Some(SockOffsets {
+        daddr: member("skc_daddr")?,
+        rcv_saddr: member("skc_rcv_saddr")?,
+        dport: member("skc_dport")?,
+        num: member("skc_num")?,
+        family: member("skc_family")?,
+        v6_daddr: member("skc_v6_daddr").unwrap_or(0),
+        v6_rcv_saddr: member("skc_v6_rcv_saddr").unwrap_or(0),
+    })
+}
+
+fn valid_stack_id(stack_id: Option<i64>) -> Option<i64> {
+    match stack_id {
+        Some(stackid) if stackid < 0 => {
+            debug!("bpf_get_stackid() returned {stackid}, dropping stack trace");
+            None
+        }
+        other => other,
     }
 }
 
@@ -0,0 +1,43 @@
+use std::{fs::File, io, os::fd::{AsRawFd, BorrowedFd, FromRawFd}};
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+const BPF_LINK_CREATE: libc::c_long = 28;
+const BPF_PERF_EVENT: u32 = 41;
+
+// struct perf_event_attr up to config2 (PERF_ATTR_SIZE_VER1), every kernel still takes it and treats
+// everything that came after as zero. flags is the bitfield with disabled, inherit etc
+#[derive(Default)]
+#[repr(C)]
+pub struct PerfEventAttr {
+    pub event_type: u32,
+    pub size: u32,
+    pub config: u64,
+    pub sample_period: u64,
+    pub sample_type: u64,
+    pub read_format: u64,
+    pub flags: u64,
+    pub wakeup_events: u32,
+    pub bp_type: u32,
+    pub config1: u64,
+    pub config2: u64,
+}
+
+// for the perf events aya cant open for us
+pub fn perf_event_open(attr: &PerfEventAttr, pid: libc::c_int, cpu: libc::c_int) -> io::Result<File> {
+    let fd = unsafe { libc::syscall(libc::SYS_perf_event_open, attr as *const PerfEventAttr, pid, cpu, -1 as libc::c_int, PERF_FLAG_FD_CLOEXEC) };
+    if fd < 0 { return Err(io::Error::last_os_error()) }
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+// links program to a perf event, closing the returned file detaches it again
+pub fn attach_program(program: BorrowedFd, perf_event: &File, cookie: u64) -> io::Result<File> {
+    // bpf_attr.link_create is prog_fd, target_fd, attach_type, flags and then the cookie
+    let mut attr = [0u8; 32];
+    attr[0..4].copy_from_slice(&(program.as_raw_fd() as u32).to_ne_bytes());
+    attr[4..8].copy_from_slice(&(perf_event.as_raw_fd() as u32).to_ne_bytes());
+    attr[8..12].copy_from_slice(&BPF_PERF_EVENT.to_ne_bytes());
+    attr[16..24].copy_from_slice(&cookie.to_ne_bytes());
+    let fd = unsafe { libc::syscall(libc::SYS_bpf, BPF_LINK_CREATE, attr.as_ptr(), attr.len() as u32) };
+    if fd < 0 { return Err(io::Error::last_os_error()) }
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
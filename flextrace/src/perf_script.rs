@@ -0,0 +1,227 @@
+use std::{collections::HashMap, io::BufRead};
+
+use anyhow::Result;
+use flextrace_common::{PerfEventType, PerfSample};
+use log::warn;
+
+use crate::{SaveData, timeline::{DEFAULT_BUCKET_NS, Timeline}};
+
+// reads the text that `perf script` prints by default, which is a header line per sample like
+//
+//   comm  pid/tid [cpu] seconds.micros: period event:modifiers:
+//
+// followed by the call chain innermost frame first, one tab indented line per frame
+//
+//   ffffffff81234567 native_safe_halt+0x7 ([kernel.kallsyms])
+//
+// and a blank line. the pid/tid, cpu and period columns are all optional depending on how perf
+// script was run, so we anchor everything on the timestamp column. the output of a long recording
+// can easily be bigger than the profile we make out of it, so it gets read a line at a time
+pub fn import_perf_script(input: impl BufRead) -> Result<SaveData> {
+    let mut save = SaveData::new();
+    let mut started = false;
+    let mut skipped: HashMap<String, u64> = HashMap::new();
+    let mut lines = input.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with("#") || line.starts_with(char::is_whitespace) { continue }
+
+        let Some((sample, event_name)) = parse_header(&line) else { continue };
+
+        let mut trace: Vec<String> = Vec::new();
+        while let Some(Ok(frame)) = lines.peek() {
+            if frame.trim().is_empty() || !frame.starts_with(char::is_whitespace) { break }
+            if let Some(name) = parse_frame(frame) { trace.push(name); }
+            lines.next();
+        }
+
+        // tracepoints, raw and pmu specific events dont have anything to be counted under
+        let Some(event_type) = perf_event_type(event_name) else {
+            *skipped.entry(event_name.split(":").next().unwrap_or("").to_string()).or_insert(0) += 1;
+            continue
+        };
+
+        // perf script goes in time order, so the first sample is where the recording starts
+        if !started {
+            save.timeline = Timeline::starting_at(sample.timestamp, DEFAULT_BUCKET_NS);
+            started = true;
+        }
+        save.record(&PerfSample { event_type, ..sample }, if trace.is_empty() { None } else { Some(trace) });
+    }
+
+    for (event, count) in skipped {
+        warn!("skipped {count} samples of {event}, flextrace has no event to count it as");
+    }
+
+    save.samples.sort_by_key(|sample| sample.timestamp);
+    save.trim_samples();
+    save.tree.update_sorted_cache();
+    Ok(save)
+}
+
+// the sample with everything but the event type filled in, and the event name to get that from
+fn parse_header(line: &str) -> Option<(PerfSample, &str)> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let time_index = tokens.iter().position(|token| token.strip_suffix(":").is_some_and(|time| time.contains(".") && time.parse::<f64>().is_ok()))?;
+
+    // everything in front of the timestamp is comm (which can have spaces in it), pid[/tid] and maybe [cpu]
+    let mut before = &tokens[..time_index];
+    let mut cpu: u32 = 0;
+    if let Some(last) = before.last() {
+        if let Some(cpu_str) = last.strip_prefix("[").and_then(|rest| rest.strip_suffix("]")) {
+            cpu = cpu_str.parse().unwrap_or(0);
+            before = &before[..before.len() - 1];
+        }
+    }
+
+    let (tgid, pid) = match before.last().map(|ids| ids.split_once("/")) {
+        Some(Some((tgid, tid))) => (tgid.parse().ok()?, tid.parse().ok()?),
+        Some(None) => {
+            let pid: u32 = before.last()?.parse().ok()?;
+            (pid, pid)
+        }
+        None => return None,
+    };
+    let comm = before[..before.len() - 1].join(" ");
+
+    // after it is an optional period and then the event name, which has its modifiers glued on with colons
+    let seconds: f64 = tokens[time_index].trim_end_matches(":").parse().ok()?;
+    let event_name = tokens[time_index + 1..].iter().find(|token| token.parse::<u64>().is_err()).copied().unwrap_or("");
+
+    let mut cmd = [0u8; 16];
+    for (index, byte) in comm.bytes().take(15).enumerate() {
+        cmd[index] = byte;
+    }
+
+    Some((PerfSample {
+        pid,
+        tgid,
+        cmd,
+        timestamp: (seconds * 1e9) as u64,
+        cpu,
+        ..Default::default()
+    }, event_name))
+}
+
+// frames look like `addr symbol+0xoffset (dso)`, we turn them into the same module:symbol(at)ip
+// names that the symbolizer makes for live recordings so everything downstream works the same
+fn parse_frame(line: &str) -> Option<String> {
+    let line = line.trim();
+    let (address, rest) = line.split_once(char::is_whitespace)?;
+    let address = u64::from_str_radix(address, 16).ok()?;
+
+    let (symbol, module) = match rest.rfind(" (") {
+        Some(paren_index) => (rest[..paren_index].trim(), rest[paren_index + 2..].trim_end_matches(")")),
+        None => (rest.trim(), ""),
+    };
+    let symbol = match symbol.rfind("+0x") {
+        Some(offset_index) => &symbol[..offset_index],
+        None => symbol,
+    };
+
+    if symbol == "[unknown]" || symbol.is_empty() {
+        return Some(format!("nosym:{address}"))
+    }
+    Some(format!("{module}:{symbol}(at){address}"))
+}
+
+// perf's names for the generic events
+fn perf_event_type(event: &str) -> Option<PerfEventType> {
+    Some(match event.split(":").next().unwrap_or("") {
+        "cycles" | "cpu-cycles" => PerfEventType::CpuCycles,
+        "instructions" => PerfEventType::Instructions,
+        "cache-references" => PerfEventType::CacheReferences,
+        "cache-misses" => PerfEventType::CacheMiss,
+        "branches" | "branch-instructions" => PerfEventType::BranchInstructions,
+        "branch-misses" => PerfEventType::BranchMisses,
+        "bus-cycles" => PerfEventType::BusCycles,
+        "stalled-cycles-frontend" | "idle-cycles-frontend" => PerfEventType::StalledCyclesFront,
+        "stalled-cycles-backend" | "idle-cycles-backend" => PerfEventType::StalledCyclesBack,
+        "ref-cycles" => PerfEventType::RefCpuCycles,
+        "cpu-clock" => PerfEventType::CpuClock,
+        "task-clock" => PerfEventType::TaskClock,
+        "page-faults" | "faults" => PerfEventType::PageFaults,
+        "context-switches" | "cs" => PerfEventType::ContextSwitches,
+        "cpu-migrations" | "migrations" => PerfEventType::CpuMigrations,
+        "minor-faults" => PerfEventType::PageFaultsMin,
+        "major-faults" => PerfEventType::PageFaultsMaj,
+        "alignment-faults" => PerfEventType::AlignmentFaults,
+        "emulation-faults" => PerfEventType::EmulationFaults,
+        "cgroup-switches" => PerfEventType::CgroupSwitches,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "\
+# ========
+# captured on    : Mon Jan  1 00:00:00 2024
+# ========
+#
+swapper     0 [002] 100.000100:     250000 cpu-clock:pppH: 
+\tffffffff81234567 native_safe_halt+0x7 ([kernel.kallsyms])
+\tffffffff81234000 default_idle+0x10 ([kernel.kallsyms])
+
+Web Content  4242/4250 [001] 100.250000: cycles:u: 
+\t7f0000001000 js::RunScript+0x20 (/usr/lib/firefox/libxul.so)
+\t55000000a000 [unknown] ([unknown])
+
+perf  4300/4300 100.300000: sched:sched_switch: prev_comm=perf
+\tffffffff81000000 __schedule+0x1 ([kernel.kallsyms])
+
+python3  777 100.400000:          1 page-faults: 
+";
+
+    #[test]
+    fn parses_headers() {
+        let (sample, event) = parse_header("Web Content  4242/4250 [001] 100.250000: cycles:u: ").unwrap();
+        assert_eq!((sample.tgid, sample.pid, sample.cpu, sample.timestamp), (4242, 4250, 1, 100_250_000_000));
+        assert_eq!(&sample.cmd[..11], b"Web Content");
+        assert_eq!(event, "cycles:u:");
+
+        let (sample, event) = parse_header("python3  777 100.400000:          1 page-faults: ").unwrap();
+        assert_eq!((sample.tgid, sample.pid, sample.cpu), (777, 777, 0));
+        assert_eq!(event, "page-faults:");
+
+        assert!(parse_header("not a sample line").is_none());
+    }
+
+    #[test]
+    fn parses_frames() {
+        assert_eq!(parse_frame("\tffffffff81234567 native_safe_halt+0x7 ([kernel.kallsyms])").unwrap(), format!("[kernel.kallsyms]:native_safe_halt(at){}", 0xffffffff81234567u64));
+        assert_eq!(parse_frame("\t55000000a000 [unknown] ([unknown])").unwrap(), format!("nosym:{}", 0x55000000a000u64));
+        assert_eq!(parse_frame("\t7f00 main (/bin/true)").unwrap(), format!("/bin/true:main(at){}", 0x7f00));
+        assert!(parse_frame("\tnot hex").is_none());
+    }
+
+    #[test]
+    fn maps_event_names() {
+        assert_eq!(perf_event_type("cycles:u:"), Some(PerfEventType::CpuCycles));
+        assert_eq!(perf_event_type("cpu-clock:pppH:"), Some(PerfEventType::CpuClock));
+        assert_eq!(perf_event_type("sched:sched_switch:"), None);
+        assert_eq!(perf_event_type("r01c2:"), None);
+    }
+
+    #[test]
+    fn imports_samples_and_skips_unknown_events() {
+        let save = import_perf_script(SCRIPT.as_bytes()).unwrap();
+
+        assert_eq!(save.samples.len(), 3);
+        assert_eq!(save.timeline.start_ns, 100_000_100_000);
+        assert_eq!(save.data[&4250].events[&PerfEventType::CpuCycles], 1);
+        assert!(!save.data.contains_key(&4300));
+
+        // outermost frame first from the root
+        let idle = save.tree.nodes[0].children[&format!("[kernel.kallsyms]:default_idle(at){}", 0xffffffff81234000u64)];
+        assert_eq!(save.tree.nodes[idle].counter(PerfEventType::CpuClock), 1);
+        assert_eq!(save.tree.nodes[idle].children.len(), 1);
+
+        // page faults had no stack, so only count towards the process
+        assert_eq!(save.data[&777].events[&PerfEventType::PageFaults], 1);
+        assert_eq!(save.timeline.rates(PerfEventType::None, 0..5).iter().sum::<u32>(), 3);
+    }
+}
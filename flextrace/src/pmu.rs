@@ -0,0 +1,287 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use flextrace_common::FlextraceError;
+use log::{debug, warn};
+use regex::Regex;
+use serde_json::Value;
+
+// intels architectural events, which every intel cpu since core 2 has, in the same format as the kernels
+// tools/perf/pmu-events tables. those are a few MB per cpu model and change with every kernel release, so
+// theyre not bundled and anything model specific has to come from a copy of them with --pmu-events
+const BUNDLED_EVENTS: &str = include_str!("../pmu-events/x86/architectural.json");
+
+const CPU_PMUS: [&str; 2] = ["/sys/bus/event_source/devices/cpu", "/sys/bus/event_source/devices/cpu_core"];
+
+// turns raw:0x1a2 or a vendor event name (INST_RETIRED.ANY_P, case doesnt matter) into the config of a
+// PERF_TYPE_RAW event. names are looked up in the cpus sysfs aliases first, then the tables in dir, then
+// the bundled ones. None means nobody has heard of it
+pub fn resolve(event: &str, dir: Option<&str>) -> Result<Option<u64>> {
+    if let Some(config) = event.strip_prefix("raw:") {
+        return Ok(Some(parse_number(config).ok_or(FlextraceError::BadArgument(event.to_string()))?))
+    }
+
+    // the topdown metrics only read out of the slots counter when theyre in a group led by it, on their own
+    // they cant be opened at all
+    if event.to_lowercase().starts_with("topdown-") {
+        return Err(FlextraceError::BadArgument(format!("{event} is a topdown metric, those only work in a group with the slots event which flextrace cant set up")).into())
+    }
+
+    if let Some(terms) = sysfs_alias(event) {
+        return Ok(encode(&terms, pmu_format))
+    }
+
+    let mut found: Option<Vec<(String, u64)>> = None;
+    if let Some(dir) = dir {
+        for table in tables(dir)? {
+            found = find_event(&table, event)?;
+            if found.is_some() { break }
+        }
+    }
+    if found.is_none() && cpuid().is_some_and(|cpuid| cpuid.starts_with("GenuineIntel")) {
+        found = find_event(BUNDLED_EVENTS, event)?;
+    }
+
+    let Some(terms) = found else { return Ok(None) };
+    let config = encode(&terms, pmu_format);
+    if let Some(config) = config {
+        debug!("{event} is raw config {config:#x}");
+    }
+    Ok(config)
+}
+
+// the kernel exports some named events of its own, like /sys/bus/event_source/devices/cpu/events/branch-misses
+// which reads event=0xc5
+fn sysfs_alias(event: &str) -> Option<Vec<(String, u64)>> {
+    CPU_PMUS.iter().find_map(|pmu| {
+        let alias = fs::read_to_string(format!("{pmu}/events/{}", event.to_lowercase())).ok()?;
+        alias.trim().split(",").map(|term| match term.split_once("=") {
+            Some((name, value)) => Some((name.to_string(), parse_number(value)?)),
+            None => Some((term.to_string(), 1)),
+        }).collect()
+    })
+}
+
+// the format terms for event out of one json table, None if the table doesnt have it
+fn find_event(table: &str, event: &str) -> Result<Option<Vec<(String, u64)>>> {
+    let entries: Vec<Value> = match serde_json::from_str(table) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("skipping pmu event table that doesnt parse: {e}");
+            return Ok(None)
+        }
+    };
+
+    let Some(entry) = entries.iter().find(|entry| entry["EventName"].as_str().is_some_and(|name| name.eq_ignore_ascii_case(event))) else { return Ok(None) };
+    if let Some(msr) = entry["MSRIndex"].as_str().filter(|msr| msr.split(",").any(|msr| parse_number(msr).unwrap_or(0) != 0)) {
+        return Err(FlextraceError::BadArgument(format!("{event} needs msr {} programmed alongside it, which flextrace cant do", msr.trim())).into())
+    }
+
+    if let Some(terms) = fixed_counter_terms(event) {
+        return Ok(Some(terms))
+    }
+
+    // the json fields jevents.py turns into format terms, minus the ones that only matter for sampling
+    // modes we dont use (pebs, precise and so on)
+    let mut terms: Vec<(String, u64)> = Vec::new();
+    for (field, term) in [("EventCode", "event"), ("UMask", "umask"), ("CounterMask", "cmask"), ("EdgeDetect", "edge"), ("Invert", "inv"), ("AnyThread", "any")] {
+        // older tables list a code per counter like "0xB7, 0xBB", the first one is fine
+        let Some(value) = entry[field].as_str().and_then(|value| parse_number(value.split(",").next()?.trim())) else { continue };
+        if value != 0 || term == "event" {
+            terms.push((term.to_string(), value));
+        }
+    }
+    Ok(if terms.is_empty() { None } else { Some(terms) })
+}
+
+// the tables list the fixed counter events as event=0x00, which is a pseudo encoding only ice lake and
+// newer understand. jevents.py swaps them for the general purpose events that count the same thing, and
+// ref cycles for the pseudo encoding every intel pmu driver knows
+fn fixed_counter_terms(event: &str) -> Option<Vec<(String, u64)>> {
+    let terms: &[(&str, u64)] = match event.to_lowercase().as_str() {
+        "inst_retired.any" | "inst_retired.any_p" => &[("event", 0xc0)],
+        "cpu_clk_unhalted.thread" | "cpu_clk_unhalted.core" => &[("event", 0x3c)],
+        "cpu_clk_unhalted.thread_any" => &[("event", 0x3c), ("any", 1)],
+        "cpu_clk_unhalted.ref" => &[("event", 0x00), ("umask", 0x03)],
+        _ => return None,
+    };
+    Some(terms.iter().map(|(term, value)| (term.to_string(), *value)).collect())
+}
+
+// dir is either one cpu models directory of json files or a whole arch directory with a mapfile.csv
+// in it (like tools/perf/pmu-events/arch/x86), in which case the row matching this cpu picks the model
+fn tables(dir: &str) -> Result<Vec<String>> {
+    let mut model_dir = Path::new(dir).to_path_buf();
+
+    if let Ok(mapfile) = fs::read_to_string(model_dir.join("mapfile.csv")) {
+        let cpuid = cpuid().ok_or(FlextraceError::Msg(String::from("could not work out the cpuid to pick a pmu event table with")))?;
+
+        let matched = match_mapfile(&mapfile, &cpuid).ok_or(FlextraceError::Msg(format!("no pmu event table in {dir} for cpu {cpuid}")))?;
+        debug!("using pmu event table {matched} for cpu {cpuid}");
+        model_dir = model_dir.join(matched);
+    }
+
+    let mut tables: Vec<String> = Vec::new();
+    for file in fs::read_dir(&model_dir)? {
+        let path = file?.path();
+        if path.extension().is_some_and(|extension| extension == "json") {
+            tables.push(fs::read_to_string(path)?);
+        }
+    }
+    Ok(tables)
+}
+
+// the model directory of the first core row in mapfile whose regex matches cpuid. perf tries with the
+// stepping first and then without it
+fn match_mapfile(mapfile: &str, cpuid: &str) -> Option<String> {
+    let without_stepping = cpuid.rsplit_once("-").map(|(rest, _)| rest).unwrap_or(cpuid);
+
+    mapfile.lines()
+        .filter(|line| !line.starts_with("#") && !line.starts_with("Family-model"))
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split(",").collect();
+            if fields.len() < 4 || fields[3].trim() != "core" { return None }
+            let pattern = Regex::new(&format!("^(?:{})$", fields[0])).ok()?;
+            if pattern.is_match(cpuid) || pattern.is_match(without_stepping) { Some(fields[2].to_string()) } else { None }
+        })
+}
+
+// vendor-family-model-stepping out of /proc/cpuinfo, like GenuineIntel-6-55-4 which is what perfs x86 mapfile matches on
+fn cpuid() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    let field = |name: &str| cpuinfo.lines()
+        .find(|line| line.split(":").next().is_some_and(|key| key.trim() == name))
+        .and_then(|line| Some(line.split_once(":")?.1.trim().to_string()));
+
+    let family: u32 = field("cpu family")?.parse().ok()?;
+    let model: u32 = field("model")?.parse().ok()?;
+    let stepping: u32 = field("stepping")?.parse().ok()?;
+    Some(format!("{}-{family}-{model:X}-{stepping:X}", field("vendor_id")?))
+}
+
+// puts each terms value into the config bits its format says it goes in, formats look like `config:0-7,32-35`
+fn encode(terms: &[(String, u64)], format_of: impl Fn(&str) -> Option<String>) -> Option<u64> {
+    let mut config: u64 = 0;
+
+    for (term, value) in terms {
+        let Some(format) = format_of(term) else {
+            warn!("unknown pmu format term {term}, ignoring it");
+            continue;
+        };
+        let Some(bits) = format.trim().strip_prefix("config:") else {
+            warn!("pmu format term {term} is not in config ({}), ignoring it", format.trim());
+            continue;
+        };
+
+        let mut value = *value;
+        for range in bits.split(",") {
+            let (start, end) = range.split_once("-").unwrap_or((range, range));
+            let (start, end): (u32, u32) = (start.parse().ok()?, end.parse().ok()?);
+            let width = end - start + 1;
+            let mask = if width >= 64 { u64::MAX } else { (1 << width) - 1 };
+            config |= (value & mask) << start;
+            value = value.checked_shr(width).unwrap_or(0);
+        }
+    }
+
+    Some(config)
+}
+
+// out of /sys/bus/event_source/devices/cpu/format, with the usual x86 layout if thats missing
+fn pmu_format(term: &str) -> Option<String> {
+    CPU_PMUS.iter().find_map(|pmu| fs::read_to_string(format!("{pmu}/format/{term}")).ok())
+        .or_else(|| default_format(term).map(String::from))
+}
+
+fn default_format(term: &str) -> Option<&'static str> {
+    match term {
+        "event" => Some("config:0-7,32-35"),
+        "umask" => Some("config:8-15"),
+        "edge" => Some("config:18"),
+        "pc" => Some("config:19"),
+        "any" => Some("config:21"),
+        "inv" => Some("config:23"),
+        "cmask" => Some("config:24-31"),
+        _ => None,
+    }
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    match value.trim().strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.trim().parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(terms: &[(&str, u64)]) -> Vec<(String, u64)> {
+        terms.iter().map(|(term, value)| (term.to_string(), *value)).collect()
+    }
+
+    #[test]
+    fn parses_numbers() {
+        assert_eq!(parse_number("0x1a2"), Some(0x1a2));
+        assert_eq!(parse_number(" 42 "), Some(42));
+        assert_eq!(parse_number("0xzz"), None);
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn encodes_terms_into_their_bits() {
+        let default = |term: &str| default_format(term).map(String::from);
+        assert_eq!(encode(&terms(&[("event", 0xc0)]), default), Some(0xc0));
+        assert_eq!(encode(&terms(&[("event", 0x2e), ("umask", 0x41), ("cmask", 1), ("inv", 1)]), default), Some(0x0180412e));
+        // event codes past 8 bits go up into 32-35
+        assert_eq!(encode(&terms(&[("event", 0x1b7)]), default), Some(0x1_0000_00b7));
+        // terms we dont know the format of get skipped
+        assert_eq!(encode(&terms(&[("event", 0x3c), ("frontend", 7)]), default), Some(0x3c));
+    }
+
+    #[test]
+    fn finds_events_in_tables() {
+        let table = r#"[
+            {"EventName": "MEM_LOAD_RETIRED.L3_MISS", "EventCode": "0xD1", "UMask": "0x20", "CounterMask": "0"},
+            {"EventName": "OLD.EVENT", "EventCode": "0xB7, 0xBB", "UMask": "0x01"},
+            {"EventName": "OFFCORE_RESPONSE", "EventCode": "0xB7", "UMask": "0x01", "MSRIndex": "0x1a6,0x1a7"},
+            {"EventName": "NO_MSR", "EventCode": "0x10", "MSRIndex": "0"}
+        ]"#;
+
+        assert_eq!(find_event(table, "mem_load_retired.l3_miss").unwrap(), Some(terms(&[("event", 0xd1), ("umask", 0x20)])));
+        assert_eq!(find_event(table, "OLD.EVENT").unwrap(), Some(terms(&[("event", 0xb7), ("umask", 0x01)])));
+        assert_eq!(find_event(table, "NO_MSR").unwrap(), Some(terms(&[("event", 0x10)])));
+        assert!(find_event(table, "OFFCORE_RESPONSE").is_err());
+        assert_eq!(find_event(table, "MISSING").unwrap(), None);
+    }
+
+    #[test]
+    fn remaps_fixed_counter_events() {
+        let find = |event| find_event(BUNDLED_EVENTS, event).unwrap();
+        assert_eq!(find("INST_RETIRED.ANY"), Some(terms(&[("event", 0xc0)])));
+        assert_eq!(find("CPU_CLK_UNHALTED.THREAD"), Some(terms(&[("event", 0x3c)])));
+        assert_eq!(find("CPU_CLK_UNHALTED.REF_TSC"), Some(terms(&[("event", 0x00), ("umask", 0x03)])));
+        assert_eq!(find("INST_RETIRED.ANY_P"), Some(terms(&[("event", 0xc0)])));
+    }
+
+    #[test]
+    fn rejects_topdown_metrics() {
+        assert!(resolve("topdown-retiring", None).is_err());
+        assert!(resolve("TOPDOWN-BE-BOUND", None).is_err());
+    }
+
+    #[test]
+    fn matches_mapfile_rows() {
+        let mapfile = "Family-model,Version,Filename,EventType\n\
+            GenuineIntel-6-(3C|45|46),v33,haswell,core\n\
+            GenuineIntel-6-55-[01234],v1.33,skylakex,core\n\
+            GenuineIntel-6-55-[56789ABCDEF],v1.21,cascadelakex,core\n\
+            GenuineIntel-6-55,v1,uncore,uncore\n";
+
+        assert_eq!(match_mapfile(mapfile, "GenuineIntel-6-45-1"), Some(String::from("haswell")));
+        assert_eq!(match_mapfile(mapfile, "GenuineIntel-6-55-4"), Some(String::from("skylakex")));
+        assert_eq!(match_mapfile(mapfile, "GenuineIntel-6-55-7"), Some(String::from("cascadelakex")));
+        assert_eq!(match_mapfile(mapfile, "GenuineIntel-6-8F-8"), None);
+    }
+}
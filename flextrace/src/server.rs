@@ -0,0 +1,167 @@
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use flextrace::{SaveData, export::{folded, json_profile_data, json_tree, openmetrics, pprof}};
+use flextrace_common::{FlextraceError, PerfEventType};
+use log::{debug, info, warn};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, sync::{mpsc::{self, Receiver, Sender}, oneshot}, time::timeout};
+
+// how long a client gets to send its request headers before we hang up on it
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub enum Route {
+    Folded(PerfEventType),
+    Pprof(PerfEventType),
+    TreeJson,
+    ProcessesJson,
+    Metrics,
+}
+
+// the tree lives in whatever loop is consuming perf samples, so the server asks that loop to
+// render the response for it instead of trying to share the tree across tasks
+pub struct ServeRequest {
+    pub route: Route,
+    pub reply: oneshot::Sender<Response>,
+}
+
+pub struct Response {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+// theres no authentication at all, so anything that isnt loopback has to be asked for with public
+pub async fn start_server(addr: SocketAddr, public: bool) -> Result<Receiver<ServeRequest>> {
+    if !addr.ip().is_loopback() {
+        if !public { return Err(FlextraceError::BadArgument(format!("refusing to serve profiles on {addr} without --http-public, anyone who can reach it could read them")).into()) }
+        warn!("serving profiles on {addr} without any authentication");
+    }
+
+    let listener = TcpListener::bind(addr).await?;
+    let (request_tx, request_rx) = mpsc::channel::<ServeRequest>(16);
+    info!("serving profiles on http://{addr}");
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("failed to accept http connection: {e}");
+                    continue;
+                }
+            };
+            debug!("http connection from {peer}");
+
+            let request_tx = request_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, request_tx).await {
+                    debug!("http connection from {peer} failed: {e}");
+                }
+            });
+        }
+    });
+
+    Ok(request_rx)
+}
+
+// waits on the request channel if there is one, and forever if the server isnt running
+pub async fn next_request(requests: &mut Option<Receiver<ServeRequest>>) -> Option<ServeRequest> {
+    match requests {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+pub fn respond(request: ServeRequest, profile: &SaveData) {
+    let tree = &profile.tree;
+    let response = match request.route {
+        Route::Folded(event) => Response { content_type: "text/plain; charset=utf-8", body: folded(tree, event).into_bytes() },
+        Route::Pprof(event) => Response { content_type: "application/octet-stream", body: pprof(tree, event) },
+        Route::TreeJson => Response { content_type: "application/json", body: json_tree(tree).to_string().into_bytes() },
+        Route::ProcessesJson => Response { content_type: "application/json", body: json_profile_data(profile).to_string().into_bytes() },
+        Route::Metrics => Response { content_type: "application/openmetrics-text; version=1.0.0; charset=utf-8", body: openmetrics(profile).into_bytes() },
+    };
+
+    // the connection might have gone away while we were busy, nothing to do about that
+    let _ = request.reply.send(response);
+}
+
+async fn handle_connection(mut stream: TcpStream, request_tx: Sender<ServeRequest>) -> Result<()> {
+    let mut buf = vec![0u8; 4096];
+    let mut len = 0;
+
+    // we only care about the request line and dont accept bodies, so read until the end of the headers
+    let headers = timeout(REQUEST_TIMEOUT, async {
+        while !buf[..len].windows(4).any(|window| window == b"\r\n\r\n") {
+            if len == buf.len() { return Ok(false) }
+
+            let read = stream.read(&mut buf[len..]).await?;
+            if read == 0 { return Ok(false) }
+            len += read;
+        }
+        Ok::<bool, std::io::Error>(true)
+    }).await;
+
+    match headers {
+        Ok(Ok(true)) => (),
+        Ok(Ok(false)) if len == buf.len() => return write_response(&mut stream, "431 Request Header Fields Too Large", "text/plain", b"headers too large\n").await,
+        Ok(Ok(false)) => return Ok(()),
+        Ok(Err(e)) => return Err(e.into()),
+        Err(_) => return write_response(&mut stream, "408 Request Timeout", "text/plain", b"took too long to send the request\n").await,
+    }
+
+    let request = String::from_utf8_lossy(&buf[..len]).to_string();
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let target = request_line.next().unwrap_or("/");
+
+    if method != "GET" {
+        return write_response(&mut stream, "405 Method Not Allowed", "text/plain", b"only GET is supported\n").await
+    }
+
+    let route = match parse_route(target) {
+        Ok(Some(route)) => route,
+        Ok(None) => return write_response(&mut stream, "404 Not Found", "text/plain", b"try /folded, /pprof, /tree.json, /processes.json or /metrics\n").await,
+        Err(e) => return write_response(&mut stream, "400 Bad Request", "text/plain", format!("{e}\n").as_bytes()).await,
+    };
+
+    let (reply_tx, reply_rx) = oneshot::channel::<Response>();
+    request_tx.send(ServeRequest { route, reply: reply_tx }).await?;
+    let response = reply_rx.await?;
+
+    write_response(&mut stream, "200 OK", response.content_type, &response.body).await
+}
+
+fn parse_route(target: &str) -> Result<Option<Route>> {
+    let (path, query) = match target.find("?") {
+        Some(question_index) => (&target[..question_index], &target[question_index + 1..]),
+        None => (target, ""),
+    };
+
+    // ?event=cpu_cycles picks which counter to use, default is the total hits
+    let mut event = PerfEventType::None;
+    for pair in query.split("&") {
+        if let Some(value) = pair.strip_prefix("event=") {
+            event = PerfEventType::from_str(&value.to_string())?;
+        }
+    }
+    if event == PerfEventType::Any { event = PerfEventType::None; }
+
+    Ok(match path {
+        "/folded" => Some(Route::Folded(event)),
+        "/pprof" => Some(Route::Pprof(event)),
+        "/tree.json" => Some(Route::TreeJson),
+        "/processes.json" => Some(Route::ProcessesJson),
+        "/metrics" => Some(Route::Metrics),
+        _ => None,
+    })
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> Result<()> {
+    let header = format!("HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
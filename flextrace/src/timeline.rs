@@ -0,0 +1,187 @@
+use std::{collections::{BTreeMap, HashMap}, ops::Range};
+
+use bincode_next::{Decode, Encode};
+use flextrace_common::{PerfEventType, PerfSample};
+
+pub const DEFAULT_BUCKET_NS: u64 = 100_000_000;
+
+// just enough to put a sample back into a tree later, leaf is the innermost node of its stack (0 if it didnt have one)
+#[derive(Debug, Copy, Clone, Encode, Decode)]
+pub struct TimedSample {
+    pub timestamp: u64,
+    pub pid: u32,
+    pub tgid: u32,
+    pub cpu: u32,
+    pub event: PerfEventType,
+    pub leaf: usize,
+    pub value: u64,
+    pub key: u64,
+    pub failed: bool,
+}
+
+impl TimedSample {
+    pub fn new(sample: &PerfSample, leaf: usize) -> Self {
+        TimedSample { timestamp: sample.timestamp, pid: sample.pid, tgid: sample.tgid, cpu: sample.cpu, event: sample.event_type, leaf, value: sample.value, key: sample.key, failed: sample.failed }
+    }
+}
+
+// samples per bucket over time, both summed over every process and split up per pid. bucket i
+// covers [start_ns + i * bucket_ns, start_ns + (i + 1) * bucket_ns) in CLOCK_MONOTONIC time,
+// which is the same clock bpf_ktime_get_ns() uses so sample timestamps line up with it. buckets
+// only exist once something lands in them, most pids are only around for a few of them
+pub type Buckets = BTreeMap<usize, u32>;
+
+#[derive(Debug, Encode, Decode)]
+pub struct Timeline {
+    pub start_ns: u64,
+    pub bucket_ns: u64,
+    pub totals: HashMap<PerfEventType, Buckets>,
+    pub per_pid: HashMap<u32, HashMap<PerfEventType, Buckets>>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline::starting_at(monotonic_ns(), DEFAULT_BUCKET_NS)
+    }
+
+    pub fn starting_at(start_ns: u64, bucket_ns: u64) -> Self {
+        Timeline { start_ns, bucket_ns, totals: HashMap::new(), per_pid: HashMap::new() }
+    }
+
+    pub fn bucket_of(&self, timestamp: u64) -> usize {
+        (timestamp.saturating_sub(self.start_ns) / self.bucket_ns) as usize
+    }
+
+    // the event gets counted under its own type and under None, which is every event together (and
+    // only once if it already was None)
+    pub fn record(&mut self, sample: &PerfSample) {
+        self.record_at(sample.timestamp, sample.pid, sample.event_type);
+    }
+
+    pub fn record_at(&mut self, timestamp: u64, pid: u32, event_type: PerfEventType) {
+        let bucket = self.bucket_of(timestamp);
+        let per_pid = self.per_pid.entry(pid).or_insert_with(HashMap::new);
+
+        let events: &[PerfEventType] = if event_type == PerfEventType::None { &[PerfEventType::None] } else { &[event_type, PerfEventType::None] };
+        for event in events {
+            bump(self.totals.entry(*event).or_default(), bucket, 1);
+            bump(per_pid.entry(*event).or_default(), bucket, 1);
+        }
+    }
+
+    // samples of event in each of the buckets, empty ones included
+    pub fn rates(&self, event: PerfEventType, buckets: Range<usize>) -> Vec<u32> {
+        let mut rates = vec![0; buckets.len()];
+        if let Some(counts) = self.totals.get(&event) {
+            for (bucket, count) in counts.range(buckets.clone()) {
+                rates[bucket - buckets.start] = *count;
+            }
+        }
+        rates
+    }
+
+    // one past the last bucket anything landed in
+    pub fn len(&self) -> usize {
+        self.totals.values().filter_map(|buckets| buckets.last_key_value()).map(|(bucket, _)| bucket + 1).max().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // timelines from different runs (or hosts) have unrelated clocks, so we line them up by how
+    // far into the recording each bucket is instead of by absolute time
+    pub fn merge(&mut self, other: &Timeline) {
+        if self.bucket_ns != other.bucket_ns { return }
+
+        for (event, buckets) in &other.totals {
+            add_buckets(self.totals.entry(*event).or_default(), buckets);
+        }
+
+        for (pid, events) in &other.per_pid {
+            let per_pid = self.per_pid.entry(*pid).or_insert_with(HashMap::new);
+            for (event, buckets) in events {
+                add_buckets(per_pid.entry(*event).or_default(), buckets);
+            }
+        }
+    }
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Timeline::new()
+    }
+}
+
+fn bump(buckets: &mut Buckets, bucket: usize, count: u32) {
+    *buckets.entry(bucket).or_insert(0) += count;
+}
+
+fn add_buckets(buckets: &mut Buckets, other: &Buckets) {
+    for (bucket, count) in other {
+        bump(buckets, *bucket, *count);
+    }
+}
+
+pub fn monotonic_ns() -> u64 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now); }
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_by_time_since_start() {
+        let mut timeline = Timeline::starting_at(1_000, 100);
+        timeline.record_at(1_000, 1, PerfEventType::CacheMiss);
+        timeline.record_at(1_099, 1, PerfEventType::CacheMiss);
+        timeline.record_at(1_350, 2, PerfEventType::CpuClock);
+        timeline.record_at(500, 2, PerfEventType::CpuClock); // before the start lands in the first bucket
+
+        assert_eq!(timeline.len(), 4);
+        assert_eq!(timeline.rates(PerfEventType::CacheMiss, 0..4), vec![2, 0, 0, 0]);
+        assert_eq!(timeline.rates(PerfEventType::CpuClock, 0..4), vec![1, 0, 0, 1]);
+        assert_eq!(timeline.rates(PerfEventType::None, 0..4), vec![3, 0, 0, 1]);
+        assert_eq!(timeline.rates(PerfEventType::None, 2..6), vec![0, 1, 0, 0]);
+        assert_eq!(timeline.per_pid[&1][&PerfEventType::CacheMiss][&0], 2);
+    }
+
+    #[test]
+    fn none_events_only_count_once() {
+        let mut timeline = Timeline::starting_at(0, 100);
+        timeline.record_at(10, 1, PerfEventType::None);
+
+        assert_eq!(timeline.rates(PerfEventType::None, 0..1), vec![1]);
+        assert_eq!(timeline.per_pid[&1][&PerfEventType::None][&0], 1);
+    }
+
+    #[test]
+    fn far_apart_samples_stay_sparse() {
+        let mut timeline = Timeline::starting_at(0, 1);
+        timeline.record_at(0, 1, PerfEventType::CpuClock);
+        timeline.record_at(1_000_000_000, 1, PerfEventType::CpuClock);
+
+        assert_eq!(timeline.len(), 1_000_000_001);
+        assert_eq!(timeline.totals[&PerfEventType::CpuClock].len(), 2);
+    }
+
+    #[test]
+    fn merge_lines_up_by_offset() {
+        let mut first = Timeline::starting_at(1_000, 100);
+        first.record_at(1_150, 1, PerfEventType::CpuClock);
+        let mut second = Timeline::starting_at(50_000, 100);
+        second.record_at(50_120, 2, PerfEventType::CpuClock);
+        second.record_at(50_250, 2, PerfEventType::CpuClock);
+
+        first.merge(&second);
+        assert_eq!(first.rates(PerfEventType::CpuClock, 0..3), vec![0, 2, 1]);
+        assert_eq!(first.per_pid[&2][&PerfEventType::CpuClock].len(), 2);
+
+        // different bucket sizes dont line up at all, so they get left out
+        first.merge(&Timeline::starting_at(0, 7));
+        assert_eq!(first.len(), 3);
+    }
+}
@@ -1,117 +1,236 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, time::{Duration, Instant}};
 
 use crossterm::event::{Event, EventStream, KeyCode};
-use flextrace_common::PerfEventType;
+use flextrace_common::{EventCount, PerfEventType};
 use futures::StreamExt;
-use flextrace::{Node, ProfileData, Tree};
-use log::{debug, trace};
-use ratatui::{Frame, Terminal, layout::{Constraint, Direction, Layout}, prelude::Backend, style::{Style, Stylize}, text::{Line, Span, Text}, widgets::{Block, Borders, Paragraph}};
-use crate::{Opt, perf::PerfManager};
+use flextrace::{SaveData, Tree, block::device_name, histogram::{Histogram, KeyedStats}, syscalls::syscall_name, timeline::TimedSample};
+use log::debug;
+use ratatui::{Frame, Terminal, layout::{Constraint, Direction, Layout}, prelude::Backend, style::{Style, Stylize}, text::{Line, Span, Text}, widgets::{Bar, BarChart, BarGroup, Block, Borders, Paragraph, Row, Sparkline, Table}};
+use tokio::sync::mpsc::Receiver;
+
+use crate::{Opt, perf::PerfManager, server::{ServeRequest, next_request, respond}};
 
 const FRAMES_PER_SECOND: f32 = 60.0;
 
 pub enum Screen {
     Main,
+    Latency,
+    Syscalls,
+    BlockIo,
+    Network,
+    Events,
+    Containers,
     Exiting,
 }
 
 pub struct State {
     pub nextid: u64,
     pub perf_manager: PerfManager,
-    pub tree: Tree,
-    pub profile_data: HashMap<u32, ProfileData>,
+    pub profile: SaveData,
     pub screen: Screen,
     pub quitting: bool,
     pub selected_event_index: usize,
     pub available_events: Vec<PerfEventType>,
     pub opt: Opt,
+    pub http_requests: Option<Receiver<ServeRequest>>,
+    pub chart_cursor: Option<usize>, // bucket index, None follows the newest bucket
+    pub window_start: Option<usize>,
+    pub window: Option<(usize, usize)>, // inclusive bucket range
+    pub window_tree: Option<Tree>,
+    pub device_names: HashMap<u64, String>, // so we dont hit sysfs every frame
+    pub counts_read_at: Instant, // counters get read once a second, not every frame
+    pub estimates: HashMap<(u32, PerfEventType), EventCount>, // profile.estimates() as of the last counter read
 }
 
 impl State {
     pub fn new(pm: PerfManager, options: Opt, event_list: Vec<PerfEventType>) -> Self {
-        let tree = Tree {
-            nodes: vec![Node { counters: HashMap::new(), name: "root".to_string(), children: HashMap::new(), hits: 0, parent: 0 }],
-            focused_event: PerfEventType::None,
-            focused_node: 0, selected_node: 0,
-            focused_children_sorted_cache: Vec::new(),
-            display_head_node: 0,
-        };
-
         State {
             nextid: 0,
             perf_manager: pm,
-            tree: tree,
-            profile_data: HashMap::new(),
+            profile: SaveData::new(),
             screen: Screen::Main,
             quitting: false,
             selected_event_index: 0,
             available_events: event_list,
             opt: options,
+            http_requests: None,
+            chart_cursor: None,
+            window_start: None,
+            window: None,
+            window_tree: None,
+            device_names: HashMap::new(),
+            counts_read_at: Instant::now(),
+            estimates: HashMap::new(),
         }
     }
+
+    pub fn select_window(&mut self, start: usize, end: usize) {
+        let (start, end) = (start.min(end), start.max(end));
+        let from = self.profile.timeline.start_ns + start as u64 * self.profile.timeline.bucket_ns;
+        let to = self.profile.timeline.start_ns + (end as u64 + 1) * self.profile.timeline.bucket_ns;
+
+        let in_window: Vec<TimedSample> = self.profile.samples.iter().filter(|sample| sample.timestamp >= from && sample.timestamp < to).copied().collect();
+        // nothing to browse, either nothing happened or its older than the samples we still have
+        if in_window.is_empty() {
+            debug!("no samples in buckets {start}..={end}, keeping the current view");
+            return
+        }
+
+        let mut window_tree = self.profile.tree.rebuild(&in_window).0;
+        window_tree.focused_event = self.profile.tree.focused_event;
+        window_tree.update_sorted_cache();
+
+        debug!("picked time window of buckets {start}..={end}, {} samples", in_window.len());
+        self.window = Some((start, end));
+        self.window_tree = Some(window_tree);
+    }
+
     pub fn handle_event(&mut self, event: &Event) {
         if let Some(key) = event.as_key_press_event() {
             match &self.screen {
                 Screen::Main => {
+                    // when a time window is picked we browse the tree rebuilt from just that window
+                    let tree = match &mut self.window_tree {
+                        Some(window_tree) => window_tree,
+                        None => &mut self.profile.tree,
+                    };
+
                     match key.code {
                         KeyCode::Char('q') => {
                             self.screen = Screen::Exiting;
                         }
                         KeyCode::Down => {
-                            if self.tree.selected_node + 1 < self.tree.focused_children_sorted_cache.len() {
-                                self.tree.selected_node += 1;
+                            if tree.selected_node + 1 < tree.focused_children_sorted_cache.len() {
+                                tree.selected_node += 1;
                             }
                         }
                         KeyCode::Up => {
-                            if self.tree.selected_node > 0 {
-                                self.tree.selected_node -= 1;
+                            if tree.selected_node > 0 {
+                                tree.selected_node -= 1;
                             }
                         }
                         KeyCode::Right => {
-                            if self.tree.focused_children_sorted_cache.len() == 0 {return}
-                            if self.tree.nodes[self.tree.focused_children_sorted_cache[self.tree.selected_node].2].children.len() == 0 {return}
-                            self.tree.focused_node = self.tree.focused_children_sorted_cache[self.tree.selected_node].2;
-                            self.tree.selected_node = 0;
-                            self.tree.update_sorted_cache();
+                            if tree.focused_children_sorted_cache.len() == 0 {return}
+                            if tree.nodes[tree.focused_children_sorted_cache[tree.selected_node].2].children.len() == 0 {return}
+                            tree.focused_node = tree.focused_children_sorted_cache[tree.selected_node].2;
+                            tree.selected_node = 0;
+                            tree.update_sorted_cache();
                         }
                         KeyCode::Left => {
-                            let old_node = self.tree.focused_node;
-                            self.tree.focused_node = self.tree.nodes[self.tree.focused_node].parent;
-                            self.tree.update_sorted_cache();
+                            let old_node = tree.focused_node;
+                            tree.focused_node = tree.nodes[tree.focused_node].parent;
+                            tree.update_sorted_cache();
 
                             // this is gonna make ts slow ill look into making it faster later, i have an idea but it uses a bit more ram
-                            self.tree.selected_node = 0;
-                            for i in 0..self.tree.focused_children_sorted_cache.len() - 1 {
-                                if self.tree.focused_children_sorted_cache[i].2 == old_node {
-                                    self.tree.selected_node = i;
-                                    break;
-                                }
-                            }
+                            tree.selected_node = tree.focused_children_sorted_cache.iter().position(|child| child.2 == old_node).unwrap_or(0);
                         }
                         KeyCode::PageDown => {
-                            if self.tree.display_head_node < self.tree.focused_children_sorted_cache.len() - 1 {
-                                self.tree.display_head_node += 1;
+                            if tree.display_head_node + 1 < tree.focused_children_sorted_cache.len() {
+                                tree.display_head_node += 1;
                             }
                         }
                         KeyCode::PageUp => {
-                            if self.tree.display_head_node > 0 {
-                                self.tree.display_head_node -= 1;
+                            if tree.display_head_node > 0 {
+                                tree.display_head_node -= 1;
                             }
                         }
                         KeyCode::Char('z') => {
                             if self.selected_event_index > 0 {
                                 self.selected_event_index -= 1;
-                                self.tree.focused_event = self.available_events[self.selected_event_index];
-                                self.tree.update_sorted_cache();
+                                tree.focused_event = self.available_events[self.selected_event_index];
+                                tree.update_sorted_cache();
                             }
                         }
                         KeyCode::Char('x') => {
                             if self.selected_event_index < self.available_events.len() - 1 {
                                 self.selected_event_index += 1;
-                                self.tree.focused_event = self.available_events[self.selected_event_index];
-                                self.tree.update_sorted_cache();
+                                tree.focused_event = self.available_events[self.selected_event_index];
+                                tree.update_sorted_cache();
                             }
                         }
+                        KeyCode::Char(',') => {
+                            let cursor = self.chart_cursor.unwrap_or(self.profile.timeline.len().saturating_sub(1));
+                            self.chart_cursor = Some(cursor.saturating_sub(1));
+                        }
+                        KeyCode::Char('.') => {
+                            let cursor = self.chart_cursor.unwrap_or(self.profile.timeline.len().saturating_sub(1));
+                            self.chart_cursor = Some((cursor + 1).min(self.profile.timeline.len().saturating_sub(1)));
+                        }
+                        KeyCode::Char('[') => {
+                            self.window_start = Some(self.chart_cursor.unwrap_or(0));
+                        }
+                        KeyCode::Char(']') => {
+                            let end = self.chart_cursor.unwrap_or(self.profile.timeline.len().saturating_sub(1));
+                            self.select_window(self.window_start.unwrap_or(0), end);
+                        }
+                        KeyCode::Char('l') => {
+                            self.screen = Screen::Latency;
+                        }
+                        KeyCode::Char('s') => {
+                            self.screen = Screen::Syscalls;
+                        }
+                        KeyCode::Char('b') => {
+                            self.screen = Screen::BlockIo;
+                        }
+                        KeyCode::Char('n') => {
+                            self.screen = Screen::Network;
+                        }
+                        KeyCode::Char('e') => {
+                            self.screen = Screen::Events;
+                        }
+                        KeyCode::Char('g') => {
+                            self.screen = Screen::Containers;
+                        }
+                        KeyCode::Char('c') => {
+                            self.window = None;
+                            self.window_tree = None;
+                            self.window_start = None;
+                            self.chart_cursor = None;
+                            self.profile.tree.focused_event = self.available_events[self.selected_event_index];
+                            self.profile.tree.update_sorted_cache();
+                        }
+                        _ => (),
+                    }
+                }
+                Screen::Latency => {
+                    match key.code {
+                        KeyCode::Char('q') => self.screen = Screen::Exiting,
+                        KeyCode::Char('l') | KeyCode::Esc => self.screen = Screen::Main,
+                        _ => (),
+                    }
+                }
+                Screen::Syscalls => {
+                    match key.code {
+                        KeyCode::Char('q') => self.screen = Screen::Exiting,
+                        KeyCode::Char('s') | KeyCode::Esc => self.screen = Screen::Main,
+                        _ => (),
+                    }
+                }
+                Screen::BlockIo => {
+                    match key.code {
+                        KeyCode::Char('q') => self.screen = Screen::Exiting,
+                        KeyCode::Char('b') | KeyCode::Esc => self.screen = Screen::Main,
+                        _ => (),
+                    }
+                }
+                Screen::Network => {
+                    match key.code {
+                        KeyCode::Char('q') => self.screen = Screen::Exiting,
+                        KeyCode::Char('n') | KeyCode::Esc => self.screen = Screen::Main,
+                        _ => (),
+                    }
+                }
+                Screen::Events => {
+                    match key.code {
+                        KeyCode::Char('q') => self.screen = Screen::Exiting,
+                        KeyCode::Char('e') | KeyCode::Esc => self.screen = Screen::Main,
+                        _ => (),
+                    }
+                }
+                Screen::Containers => {
+                    match key.code {
+                        KeyCode::Char('q') => self.screen = Screen::Exiting,
+                        KeyCode::Char('g') | KeyCode::Esc => self.screen = Screen::Main,
                         _ => (),
                     }
                 }
@@ -144,37 +263,23 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut State) ->
     loop {
         tokio::select! {
             Some(recv) = app.perf_manager.event_rx.recv() => {
-                if let Some(stackid) = recv.stack_id {
-                    if stackid < 0 {
-                        debug!("bpf_get_stackid() returned {stackid}, dropping stack trace");
-                    }
-                    else {
-                        let trace = app.perf_manager.get_stack_fp(stackid)?;
-                        trace!("generated stack trace from stackid {stackid}");
-
-                        app.tree.update(app.perf_manager.symbolize_fp_trace(trace, recv.pid)?, recv.event_type);
-                        app.tree.update_sorted_cache();
-                    }
+                let trace = app.perf_manager.trace_for_sample(&recv)?;
+                if app.profile.record(&recv, trace) != 0 {
+                    app.profile.tree.update_sorted_cache();
                 }
-
-                let event_type = recv.event_type;
-                let pid = recv.pid;
-                let recv_gid = recv.gid;
-
-                let profile_data_entry = app.profile_data.entry(pid).or_insert_with(||
-                    ProfileData {
-                        events: HashMap::new(),
-                        name: String::from_utf8_lossy(&recv.cmd).to_string(),
-                        gid: 0,
-                    }
-                );
-                
-                // increment the counter for that event
-                *profile_data_entry.events.entry(event_type).or_insert(0) += 1;
-                profile_data_entry.gid = recv_gid;
             },
+            Some(request) = next_request(&mut app.http_requests) => respond(request, &app.profile),
             Some(Ok(event)) = events.next() => app.handle_event(&event),
-            _ = interval.tick() => { terminal.draw(|f| render(f, app)); }
+            _ = interval.tick() => {
+                if app.counts_read_at.elapsed() >= Duration::from_secs(1) {
+                    app.profile.counts = app.perf_manager.read_counts();
+                    app.perf_manager.record_allocs(&mut app.profile);
+                    app.profile.tree.update_sorted_cache();
+                    app.estimates = app.profile.estimates();
+                    app.counts_read_at = Instant::now();
+                }
+                terminal.draw(|f| render(f, app));
+            }
         }
 
         if app.quitting {
@@ -187,19 +292,15 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut State) ->
 pub fn render(f: &mut Frame, app: &mut State) {
     match app.screen {
         Screen::Main => {
-            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Length(2), Constraint::Fill(1), Constraint::Length(2)]).split(f.area());
-            let event_string = {
-                if let Some(name) = app.available_events[app.selected_event_index].ebpf_from_self() {
-                    name
-                }
-                else { "None".to_string() }
-            };
+            let tree = app.window_tree.as_ref().unwrap_or(&app.profile.tree);
+            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Length(2), Constraint::Fill(1), Constraint::Length(5), Constraint::Length(2)]).split(f.area());
+            let event_string = app.profile.label(app.available_events[app.selected_event_index]);
 
             let title = Line::from(vec![
-                Span::raw("  focused function: ".to_owned() + &app.tree.nodes[app.tree.focused_node].name),
-                Span::raw("  # children: ".to_owned() + &app.tree.nodes[app.tree.focused_node].children.len().to_string()),
+                Span::raw("  focused function: ".to_owned() + &tree.nodes[tree.focused_node].name),
+                Span::raw("  # children: ".to_owned() + &tree.nodes[tree.focused_node].children.len().to_string()),
                 Span::raw("  focused event: ".to_string() + &event_string),
-                Span::raw("  selected: ".to_owned() + &app.tree.focused_children_sorted_cache[app.tree.selected_node].0)
+                Span::raw("  selected: ".to_owned() + tree.focused_children_sorted_cache.get(tree.selected_node).map(|child| child.0.as_str()).unwrap_or(""))
 
             ]);
 
@@ -209,8 +310,82 @@ pub fn render(f: &mut Frame, app: &mut State) {
             ]);
 
             f.render_widget(title, layout_chunks[0]);
-            f.render_widget(&app.tree, layout_chunks[1]);
-            f.render_widget(footer, layout_chunks[2]);
+            f.render_widget(tree, layout_chunks[1]);
+            f.render_widget(rate_sparkline(app, layout_chunks[2].width), layout_chunks[2]);
+            f.render_widget(footer, layout_chunks[3]);
+        },
+        Screen::Latency => {
+            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Fill(1), Constraint::Length(2)]).split(f.area());
+
+            let footer = Line::from(vec![
+                Span::raw(" flextrace pre alpha ").red(),
+                Span::raw(" latency per process (l to go back) ").blue(),
+            ]);
+
+            f.render_widget(latency_table(app), layout_chunks[0]);
+            f.render_widget(footer, layout_chunks[1]);
+        },
+        Screen::Syscalls => {
+            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Fill(1), Constraint::Length(2)]).split(f.area());
+
+            let footer = Line::from(vec![
+                Span::raw(" flextrace pre alpha ").red(),
+                Span::raw(" syscalls, needs --syscalls (s to go back) ").blue(),
+            ]);
+
+            f.render_widget(syscall_table(app), layout_chunks[0]);
+            f.render_widget(footer, layout_chunks[1]);
+        },
+        Screen::BlockIo => {
+            if let Some(devices) = app.profile.breakdowns.get(&PerfEventType::BlockIo) {
+                for dev in devices.keys() {
+                    app.device_names.entry(*dev).or_insert_with(|| device_name(*dev));
+                }
+            }
+            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Percentage(35), Constraint::Length(10), Constraint::Fill(1), Constraint::Length(2)]).split(f.area());
+
+            let footer = Line::from(vec![
+                Span::raw(" flextrace pre alpha ").red(),
+                Span::raw(" block io, needs --block-io (b to go back) ").blue(),
+            ]);
+
+            f.render_widget(block_device_table(app), layout_chunks[0]);
+            f.render_widget(block_latency_chart(app), layout_chunks[1]);
+            f.render_widget(block_process_table(app), layout_chunks[2]);
+            f.render_widget(footer, layout_chunks[3]);
+        },
+        Screen::Network => {
+            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Fill(1), Constraint::Length(2)]).split(f.area());
+
+            let footer = Line::from(vec![
+                Span::raw(" flextrace pre alpha ").red(),
+                Span::raw(" tcp connections, needs --tcp (n to go back) ").blue(),
+            ]);
+
+            f.render_widget(network_table(app), layout_chunks[0]);
+            f.render_widget(footer, layout_chunks[1]);
+        },
+        Screen::Events => {
+            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Fill(1), Constraint::Length(2)]).split(f.area());
+
+            let footer = Line::from(vec![
+                Span::raw(" flextrace pre alpha ").red(),
+                Span::raw(" events per process, estimated from the perf counters (e to go back) ").blue(),
+            ]);
+
+            f.render_widget(events_table(app), layout_chunks[0]);
+            f.render_widget(footer, layout_chunks[1]);
+        },
+        Screen::Containers => {
+            let layout_chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Fill(1), Constraint::Length(2)]).split(f.area());
+
+            let footer = Line::from(vec![
+                Span::raw(" flextrace pre alpha ").red(),
+                Span::raw(" events per container or cgroup (g to go back) ").blue(),
+            ]);
+
+            f.render_widget(containers_table(app), layout_chunks[0]);
+            f.render_widget(footer, layout_chunks[1]);
         },
         Screen::Exiting => {
             let span = Span::raw("are you sure you want to exit? (q)");
@@ -218,3 +393,336 @@ pub fn render(f: &mut Frame, app: &mut State) {
         }
     }
 }
+
+// the newest buckets that fit on screen, one column per bucket, unless the cursor has been moved
+// further back than that in which case the view scrolls back with it
+fn rate_sparkline(app: &State, width: u16) -> Sparkline<'static> {
+    let timeline = &app.profile.timeline;
+    let len = timeline.len();
+    let visible = len.min(width.saturating_sub(2) as usize);
+
+    let view_end = match app.chart_cursor {
+        Some(cursor) if cursor + visible < len => cursor + visible,
+        _ => len,
+    };
+    let data: Vec<u64> = timeline.rates(app.available_events[app.selected_event_index], view_end - visible..view_end).iter().map(|&count| count as u64).collect();
+
+    let seconds = |bucket: usize| bucket as f64 * timeline.bucket_ns as f64 / 1e9;
+    let mut title = format!(" samples per {}ms ", timeline.bucket_ns / 1_000_000);
+    if let Some(cursor) = app.chart_cursor {
+        title += &format!("| cursor {:.1}s ", seconds(cursor));
+    }
+    if let Some(start) = app.window_start {
+        title += &format!("| start {:.1}s ", seconds(start));
+    }
+    if let Some((start, end)) = app.window {
+        title += &format!("| showing {:.1}s-{:.1}s (c to clear) ", seconds(start), seconds(end + 1));
+    }
+
+    Sparkline::default().block(Block::default().borders(Borders::ALL).title(title)).data(data)
+}
+
+// every histogram we have (one per process and probe), the ones with the most total time first
+fn latency_table(app: &State) -> Table<'static> {
+    let mut entries: Vec<(u32, PerfEventType, &Histogram)> = Vec::new();
+    for (pid, events) in &app.profile.histograms {
+        for (event, histogram) in events {
+            entries.push((*pid, *event, histogram));
+        }
+    }
+    entries.sort_by_key(|(_, _, histogram)| std::cmp::Reverse(histogram.sum));
+
+    let rows: Vec<Row> = entries.iter().map(|(pid, event, histogram)| {
+        let comm = app.profile.data.get(pid).map(|data| data.name.trim_end_matches('\0').to_string()).unwrap_or_default();
+        Row::new(vec![
+            pid_label(app, *pid),
+            comm,
+            app.profile.label(*event),
+            histogram.count.to_string(),
+            format_value(*event, histogram.sum),
+            format_value(*event, histogram.mean()),
+            format_value(*event, histogram.percentile(50.0)),
+            format_value(*event, histogram.percentile(99.0)),
+            format_value(*event, histogram.max),
+        ])
+    }).collect();
+
+    let widths = [
+        Constraint::Length(16), Constraint::Length(16), Constraint::Fill(1), Constraint::Length(10),
+        Constraint::Length(10), Constraint::Length(10), Constraint::Length(10), Constraint::Length(10), Constraint::Length(10),
+    ];
+
+    Table::new(rows, widths)
+        .header(Row::new(vec!["pid", "comm", "probe", "calls", "total", "avg", "p50", "p99", "max"]).bold())
+        .block(Block::default().borders(Borders::ALL))
+}
+
+// every syscall summed over all processes, the most total time first. the process column is
+// whoever spent the most time in that syscall
+fn syscall_table(app: &State) -> Table<'static> {
+    let mut entries = app.profile.breakdown_totals(PerfEventType::Syscall);
+    entries.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.latency.sum));
+
+    let rows: Vec<Row> = entries.iter().map(|(number, stats)| {
+        let top_pid = app.profile.breakdowns.get(&PerfEventType::Syscall).and_then(|keys| keys.get(number))
+            .and_then(|pids| pids.iter().max_by_key(|(_, stats)| stats.latency.sum)).map(|(pid, _)| *pid);
+        let top_process = match top_pid {
+            Some(pid) => format!("{pid} {}", app.profile.data.get(&pid).map(|data| data.name.trim_end_matches('\0').to_string()).unwrap_or_default()),
+            None => String::new(),
+        };
+
+        Row::new(vec![
+            syscall_name(*number),
+            stats.latency.count.to_string(),
+            stats.errors.to_string(),
+            format_ns(stats.latency.sum),
+            format_ns(stats.latency.mean()),
+            format_ns(stats.latency.percentile(99.0)),
+            format_ns(stats.latency.max),
+            top_process,
+        ])
+    }).collect();
+
+    let widths = [
+        Constraint::Length(20), Constraint::Length(10), Constraint::Length(8), Constraint::Length(10),
+        Constraint::Length(10), Constraint::Length(10), Constraint::Length(10), Constraint::Fill(1),
+    ];
+
+    Table::new(rows, widths)
+        .header(Row::new(vec!["syscall", "calls", "errors", "total", "avg", "p99", "max", "top process"]).bold())
+        .block(Block::default().borders(Borders::ALL))
+}
+
+fn block_device_table(app: &State) -> Table<'static> {
+    let mut entries = app.profile.breakdown_totals(PerfEventType::BlockIo);
+    entries.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.latency.sum));
+
+    let rows: Vec<Row> = entries.iter().map(|(dev, stats)| {
+        Row::new(vec![
+            app.device_names.get(dev).cloned().unwrap_or_default(),
+            stats.latency.count.to_string(),
+            stats.errors.to_string(),
+            format_ns(stats.latency.sum),
+            format_ns(stats.latency.mean()),
+            format_ns(stats.latency.percentile(50.0)),
+            format_ns(stats.latency.percentile(99.0)),
+            format_ns(stats.latency.max),
+        ])
+    }).collect();
+
+    let widths = [
+        Constraint::Fill(1), Constraint::Length(10), Constraint::Length(8), Constraint::Length(10),
+        Constraint::Length(10), Constraint::Length(10), Constraint::Length(10), Constraint::Length(10),
+    ];
+
+    Table::new(rows, widths)
+        .header(Row::new(vec!["device", "requests", "errors", "total", "avg", "p50", "p99", "max"]).bold())
+        .block(Block::default().borders(Borders::ALL).title(" per device "))
+}
+
+// the latency distribution of the device with the most total time, one bar per log2 bucket
+fn block_latency_chart(app: &State) -> BarChart<'static> {
+    let entries = app.profile.breakdown_totals(PerfEventType::BlockIo);
+    let busiest = entries.iter().max_by_key(|(_, stats)| stats.latency.sum);
+
+    let (title, bars) = match busiest {
+        Some((dev, stats)) => {
+            let bars: Vec<Bar> = stats.latency.buckets.iter().enumerate().skip_while(|(_, count)| **count == 0)
+                .map(|(bucket, count)| Bar::default().value(*count).text_value(count.to_string()).label(Line::from(format_ns(if bucket == 0 { 0 } else { 1u64 << (bucket - 1) }))))
+                .collect();
+            (format!(" latency histogram for {} ", app.device_names.get(dev).cloned().unwrap_or_default()), bars)
+        }
+        None => (String::from(" latency histogram "), Vec::new()),
+    };
+
+    BarChart::default().block(Block::default().borders(Borders::ALL).title(title)).bar_width(8).bar_gap(1).data(BarGroup::default().bars(&bars))
+}
+
+fn block_process_table(app: &State) -> Table<'static> {
+    let mut entries: Vec<(u64, u32, &KeyedStats)> = Vec::new();
+    if let Some(devices) = app.profile.breakdowns.get(&PerfEventType::BlockIo) {
+        for (dev, pids) in devices {
+            for (pid, stats) in pids {
+                entries.push((*dev, *pid, stats));
+            }
+        }
+    }
+    entries.sort_by_key(|(_, _, stats)| std::cmp::Reverse(stats.latency.sum));
+
+    let rows: Vec<Row> = entries.iter().map(|(dev, pid, stats)| {
+        let comm = app.profile.data.get(pid).map(|data| data.name.trim_end_matches('\0').to_string()).unwrap_or_default();
+        Row::new(vec![
+            pid_label(app, *pid),
+            comm,
+            app.device_names.get(dev).cloned().unwrap_or_default(),
+            stats.latency.count.to_string(),
+            format_ns(stats.latency.sum),
+            format_ns(stats.latency.mean()),
+            format_ns(stats.latency.percentile(99.0)),
+            format_ns(stats.latency.max),
+        ])
+    }).collect();
+
+    let widths = [
+        Constraint::Length(16), Constraint::Length(16), Constraint::Fill(1), Constraint::Length(10),
+        Constraint::Length(10), Constraint::Length(10), Constraint::Length(10), Constraint::Length(10),
+    ];
+
+    Table::new(rows, widths)
+        .header(Row::new(vec!["pid", "comm", "device", "requests", "total", "avg", "p99", "max"]).bold())
+        .block(Block::default().borders(Borders::ALL).title(" per process "))
+}
+
+// one row per process and connection, the most traffic first
+fn network_table(app: &State) -> Table<'static> {
+    // sent bytes, received bytes, retransmits
+    let mut connections: HashMap<(u32, u64), (u64, u64, u64)> = HashMap::new();
+    for event in [PerfEventType::TcpSend, PerfEventType::TcpRecv, PerfEventType::TcpRetransmit] {
+        let Some(keys) = app.profile.breakdowns.get(&event) else { continue };
+        for (key, pids) in keys {
+            for (pid, stats) in pids {
+                let entry = connections.entry((*pid, *key)).or_insert((0, 0, 0));
+                match event {
+                    PerfEventType::TcpSend => entry.0 += stats.latency.sum,
+                    PerfEventType::TcpRecv => entry.1 += stats.latency.sum,
+                    _ => entry.2 += stats.latency.count,
+                }
+            }
+        }
+    }
+
+    let mut entries: Vec<((u32, u64), (u64, u64, u64))> = connections.into_iter().collect();
+    entries.sort_by_key(|(_, (sent, received, _))| std::cmp::Reverse(sent + received));
+
+    let rows: Vec<Row> = entries.iter().map(|((pid, key), (sent, received, retransmits))| {
+        let comm = app.profile.data.get(pid).map(|data| data.name.trim_end_matches('\0').to_string()).unwrap_or_default();
+        let socket = app.profile.sockets.get(key);
+        Row::new(vec![
+            pid_label(app, *pid),
+            comm,
+            socket.map(|socket| socket.local()).unwrap_or_default(),
+            socket.map(|socket| socket.remote()).unwrap_or_default(),
+            format_bytes(*sent),
+            format_bytes(*received),
+            retransmits.to_string(),
+        ])
+    }).collect();
+
+    let widths = [
+        Constraint::Length(16), Constraint::Length(16), Constraint::Fill(1), Constraint::Fill(1),
+        Constraint::Length(12), Constraint::Length(12), Constraint::Length(12),
+    ];
+
+    Table::new(rows, widths)
+        .header(Row::new(vec!["pid", "comm", "local", "remote", "sent", "received", "retransmits"]).bold())
+        .block(Block::default().borders(Borders::ALL))
+}
+
+// sample hits next to what the counters say actually happened, which unlike hits doesnt depend on the
+// sampling period so it can be compared between runs
+fn events_table(app: &State) -> Table<'static> {
+    let mut entries: Vec<(u32, PerfEventType, u32)> = Vec::new();
+    for (pid, data) in &app.profile.data {
+        for (event, hits) in &data.events {
+            entries.push((*pid, *event, *hits));
+        }
+    }
+    entries.sort_by_key(|(_, _, hits)| std::cmp::Reverse(*hits));
+
+    let rows: Vec<Row> = entries.iter().map(|(pid, event, hits)| {
+        let comm = app.profile.data.get(pid).map(|data| data.name.trim_end_matches('\0').to_string()).unwrap_or_default();
+        let estimate = app.estimates.get(&(*pid, *event));
+        Row::new(vec![
+            pid_label(app, *pid),
+            comm,
+            app.profile.label(*event),
+            app.profile.sampling.get(event).map(|sampling| sampling.to_string()).unwrap_or_default(),
+            hits.to_string(),
+            estimate.map(|estimate| format!("≈{}", format_count(estimate.value as f64))).unwrap_or_default(),
+            estimate.map(|estimate| format!("≈{}/s", format_count(estimate.per_second()))).unwrap_or_default(),
+        ])
+    }).collect();
+
+    let widths = [
+        Constraint::Length(16), Constraint::Length(16), Constraint::Fill(1), Constraint::Length(12),
+        Constraint::Length(10), Constraint::Length(12), Constraint::Length(12),
+    ];
+
+    Table::new(rows, widths)
+        .header(Row::new(vec!["pid", "comm", "event", "sampling", "hits", "estimated", "rate"]).bold())
+        .block(Block::default().borders(Borders::ALL))
+}
+
+// the processes of each cgroup added together, which is what stays the same when a container gets
+// redeployed and all its pids change
+fn containers_table(app: &State) -> Table<'static> {
+    // (processes, hits, estimated) by cgroup and event
+    let mut groups: HashMap<(u64, PerfEventType), (u32, u64, Option<u64>)> = HashMap::new();
+    for (pid, data) in &app.profile.data {
+        for (event, hits) in &data.events {
+            let entry = groups.entry((data.cgroup, *event)).or_insert((0, 0, None));
+            entry.0 += 1;
+            entry.1 += *hits as u64;
+            if let Some(estimate) = app.estimates.get(&(*pid, *event)) {
+                entry.2 = Some(entry.2.unwrap_or(0) + estimate.value);
+            }
+        }
+    }
+
+    let mut entries: Vec<((u64, PerfEventType), (u32, u64, Option<u64>))> = groups.into_iter().collect();
+    entries.sort_by_key(|(_, (_, hits, _))| std::cmp::Reverse(*hits));
+
+    let rows: Vec<Row> = entries.iter().map(|((cgroup, event), (processes, hits, estimate))| {
+        Row::new(vec![
+            app.profile.cgroups.get(cgroup).cloned().unwrap_or(format!("cgroup {cgroup}")),
+            app.profile.label(*event),
+            processes.to_string(),
+            hits.to_string(),
+            estimate.map(|estimate| format!("≈{}", format_count(estimate as f64))).unwrap_or_default(),
+        ])
+    }).collect();
+
+    let widths = [Constraint::Fill(2), Constraint::Fill(1), Constraint::Length(10), Constraint::Length(10), Constraint::Length(12)];
+
+    Table::new(rows, widths)
+        .header(Row::new(vec!["container", "event", "processes", "hits", "estimated"]).bold())
+        .block(Block::default().borders(Borders::ALL))
+}
+
+// host pid, with the one the process sees for itself next to it when its in its own pid namespace
+fn pid_label(app: &State, pid: u32) -> String {
+    match app.profile.data.get(&pid).map(|data| data.ns_pid) {
+        Some(ns_pid) if ns_pid != 0 && ns_pid != pid => format!("{pid} ({ns_pid})"),
+        _ => pid.to_string(),
+    }
+}
+
+fn format_count(count: f64) -> String {
+    if count >= 1e9 { format!("{:.1}G", count / 1e9) }
+    else if count >= 1e6 { format!("{:.1}M", count / 1e6) }
+    else if count >= 1e3 { format!("{:.1}K", count / 1e3) }
+    else { format!("{count:.0}") }
+}
+
+// histograms hold ns for latencies, bytes for sizes and plain numbers for usdt arguments
+fn format_value(event: PerfEventType, value: u64) -> String {
+    match event.value_unit() {
+        "bytes" => format_bytes(value),
+        "nanoseconds" => format_ns(value),
+        _ => value.to_string(),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1 << 30 { format!("{:.2}GiB", bytes as f64 / (1u64 << 30) as f64) }
+    else if bytes >= 1 << 20 { format!("{:.2}MiB", bytes as f64 / (1u64 << 20) as f64) }
+    else if bytes >= 1 << 10 { format!("{:.2}KiB", bytes as f64 / (1u64 << 10) as f64) }
+    else { format!("{bytes}B") }
+}
+
+fn format_ns(ns: u64) -> String {
+    if ns >= 1_000_000_000 { format!("{:.2}s", ns as f64 / 1e9) }
+    else if ns >= 1_000_000 { format!("{:.2}ms", ns as f64 / 1e6) }
+    else if ns >= 1_000 { format!("{:.2}us", ns as f64 / 1e3) }
+    else { format!("{ns}ns") }
+}
@@ -0,0 +1,291 @@
+use std::{ffi::CString, fs::{self, File}, io, mem::size_of, os::fd::BorrowedFd};
+
+use anyhow::Result;
+use flextrace_common::UsdtArgSpec;
+#[cfg(target_arch = "x86_64")]
+use flextrace_common::{USDT_ARG_CONST, USDT_ARG_DEREF, USDT_ARG_REG};
+use log::{debug, warn};
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol};
+
+use crate::perf_event::{self, PerfEventAttr};
+
+// one probe site out of a binarys .note.stapsdt section, the same provider:name can show up at
+// several sites (inlining, multiple call sites) and each one gets its own uprobe
+#[derive(Debug, Clone)]
+pub struct UsdtNote {
+    pub provider: String,
+    pub name: String,
+    pub offset: u64, // file offset of the probe site, which is what uprobes want
+    pub semaphore_offset: Option<u64>, // file offset of the semaphore if the probe has one
+    pub args: Vec<(String, Option<UsdtArgSpec>)>, // the raw spec and how to fetch it, None if we cant
+}
+
+pub fn read_notes(path: &str) -> Result<Vec<UsdtNote>> {
+    parse_notes(&fs::read(path)?)
+}
+
+// each note is namesz, descsz, type, the name ("stapsdt") and then a desc of
+//
+//   pc, link time base, semaphore address, "provider\0name\0args\0"
+//
+// where the three addresses are pointer sized. if the binary was prelinked the pc is off by however much
+// .stapsdt.base moved, see https://sourceware.org/systemtap/wiki/UserSpaceProbeImplementation
+pub fn parse_notes(data: &[u8]) -> Result<Vec<UsdtNote>> {
+    let file = object::File::parse(data)?;
+
+    let Some(section) = file.section_by_name(".note.stapsdt") else { return Ok(Vec::new()) };
+    let notes = section.data()?;
+    let base_address = file.section_by_name(".stapsdt.base").map(|base| base.address());
+
+    // args like 8@counter(%rip) name a symbol instead of giving a number
+    let symbol_address = |name: &str| {
+        file.symbols().chain(file.dynamic_symbols()).find(|symbol| symbol.name() == Ok(name)).map(|symbol| symbol.address())
+    };
+
+    let little_endian = file.is_little_endian();
+    let read_u32 = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes[..4].try_into().unwrap();
+        if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }
+    };
+    let address_size = if file.is_64() { 8 } else { 4 };
+    let read_address = |bytes: &[u8]| -> u64 {
+        if address_size == 4 { return read_u32(bytes) as u64 }
+        let bytes: [u8; 8] = bytes[..8].try_into().unwrap();
+        if little_endian { u64::from_le_bytes(bytes) } else { u64::from_be_bytes(bytes) }
+    };
+
+    let mut parsed: Vec<UsdtNote> = Vec::new();
+    let mut index = 0;
+
+    while index + 12 <= notes.len() {
+        let name_size = read_u32(&notes[index..]) as usize;
+        let desc_size = read_u32(&notes[index + 4..]) as usize;
+        let note_type = read_u32(&notes[index + 8..]);
+
+        let name_start = index + 12;
+        let desc_start = name_start + align4(name_size);
+        index = desc_start + align4(desc_size);
+        if index > notes.len() { break }
+
+        if note_type != 3 || &notes[name_start..name_start + name_size] != b"stapsdt\0" { continue }
+
+        let desc = &notes[desc_start..desc_start + desc_size];
+        if desc.len() < address_size * 3 { continue }
+
+        let mut pc = read_address(desc);
+        let link_base = read_address(&desc[address_size..]);
+        let semaphore = read_address(&desc[address_size * 2..]);
+        if let Some(base_address) = base_address {
+            pc = pc.wrapping_add(base_address).wrapping_sub(link_base);
+        }
+
+        let mut strings = desc[address_size * 3..].split(|byte| *byte == 0).map(|bytes| String::from_utf8_lossy(bytes).to_string());
+        let provider = strings.next().unwrap_or_default();
+        let name = strings.next().unwrap_or_default();
+        let args = strings.next().unwrap_or_default().split_whitespace()
+            .map(|spec| (spec.to_string(), parse_arg_spec(spec, pc, &symbol_address)))
+            .collect();
+
+        let Some(offset) = file_offset(&file, pc) else {
+            warn!("usdt probe {provider}:{name} at {pc:#x} is not in any loadable segment, skipping it");
+            continue;
+        };
+
+        parsed.push(UsdtNote {
+            provider,
+            name,
+            offset,
+            semaphore_offset: if semaphore == 0 { None } else { file_offset(&file, semaphore) },
+            args,
+        });
+    }
+
+    Ok(parsed)
+}
+
+fn align4(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+fn file_offset(file: &object::File, address: u64) -> Option<u64> {
+    file.segments().find_map(|segment| {
+        let (offset, size) = segment.file_range();
+        if address >= segment.address() && address < segment.address() + size {
+            Some(address - segment.address() + offset)
+        }
+        else { None }
+    })
+}
+
+// arg specs look like `-4@%edi`, `8@-16(%rbp)`, `4@$42` or `8@counter+8(%rip)`, size@operand in gas
+// syntax. pc is the probe sites address, which is also where rip points when the uprobe fires, so
+// symbols relative to rip turn into a plain displacement from it. the registers are x86_64 ones
+#[cfg(target_arch = "x86_64")]
+pub fn parse_arg_spec(spec: &str, pc: u64, symbol_address: impl Fn(&str) -> Option<u64>) -> Option<UsdtArgSpec> {
+    let (size, operand) = spec.split_once("@")?;
+    let mut parsed = UsdtArgSpec { size: size.parse::<i8>().ok()?, ..Default::default() };
+
+    if let Some(constant) = operand.strip_prefix("$") {
+        parsed.kind = USDT_ARG_CONST;
+        parsed.value = constant.parse::<i64>().ok()?;
+    }
+    else if let Some(register) = operand.strip_prefix("%") {
+        parsed.kind = USDT_ARG_REG;
+        parsed.reg_offset = pt_regs_offset(register)?;
+    }
+    else {
+        let (displacement, register) = operand.strip_suffix(")")?.split_once("(%")?;
+        parsed.kind = USDT_ARG_DEREF;
+        parsed.reg_offset = pt_regs_offset(register)?;
+        parsed.value = match displacement.parse::<i64>() {
+            _ if displacement.is_empty() => 0,
+            Ok(value) => value,
+            Err(_) if register == "rip" => symbol_displacement(displacement, pc, symbol_address)?,
+            Err(_) => return None,
+        };
+    }
+
+    Some(parsed)
+}
+
+// nothing but the x86_64 pt_regs layout is known here, so on anything else no arg can be fetched
+#[cfg(not(target_arch = "x86_64"))]
+pub fn parse_arg_spec(_spec: &str, _pc: u64, _symbol_address: impl Fn(&str) -> Option<u64>) -> Option<UsdtArgSpec> {
+    None
+}
+
+// `symbol`, `symbol+8` or `symbol-8` as a distance from pc
+#[cfg(target_arch = "x86_64")]
+fn symbol_displacement(displacement: &str, pc: u64, symbol_address: impl Fn(&str) -> Option<u64>) -> Option<i64> {
+    let (symbol, offset) = match displacement.rfind(['+', '-']) {
+        Some(split) if split > 0 => (&displacement[..split], displacement[split..].trim_start_matches("+").parse::<i64>().ok()?),
+        _ => (displacement, 0),
+    };
+    Some(symbol_address(symbol)?.wrapping_sub(pc) as i64 + offset)
+}
+
+// where each register lives in the x86_64 struct pt_regs, every width of a register reads the full one
+// and the size in the arg spec takes care of cutting it down
+#[cfg(target_arch = "x86_64")]
+fn pt_regs_offset(register: &str) -> Option<u16> {
+    let index = match register {
+        "r15" | "r15d" | "r15w" | "r15b" => 0,
+        "r14" | "r14d" | "r14w" | "r14b" => 1,
+        "r13" | "r13d" | "r13w" | "r13b" => 2,
+        "r12" | "r12d" | "r12w" | "r12b" => 3,
+        "rbp" | "ebp" | "bp" | "bpl" => 4,
+        "rbx" | "ebx" | "bx" | "bl" => 5,
+        "r11" | "r11d" | "r11w" | "r11b" => 6,
+        "r10" | "r10d" | "r10w" | "r10b" => 7,
+        "r9" | "r9d" | "r9w" | "r9b" => 8,
+        "r8" | "r8d" | "r8w" | "r8b" => 9,
+        "rax" | "eax" | "ax" | "al" => 10,
+        "rcx" | "ecx" | "cx" | "cl" => 11,
+        "rdx" | "edx" | "dx" | "dl" => 12,
+        "rsi" | "esi" | "si" | "sil" => 13,
+        "rdi" | "edi" | "di" | "dil" => 14,
+        "rip" => 16,
+        "rsp" | "esp" | "sp" | "spl" => 19,
+        _ => return None,
+    };
+    Some(index * 8)
+}
+
+const UPROBE_REF_CTR_SHIFT: u64 = 32; // /sys/bus/event_source/devices/uprobe/format/ref_ctr_offset
+
+// a uprobe on one probe site opened straight through the uprobe pmu, since thats the only way to hand
+// the kernel the semaphore (ref_ctr_offset). the kernel then bumps the semaphore in every process that
+// maps target, including ones that start later, and drops it again when the probe goes away.
+// closing both files detaches it
+pub struct UsdtProbe {
+    _perf_event: File,
+    _link: File,
+}
+
+impl UsdtProbe {
+    pub fn attach(program: BorrowedFd, target: &str, note: &UsdtNote, pid: Option<i32>, cookie: u64) -> io::Result<Self> {
+        let pmu_type: u32 = fs::read_to_string("/sys/bus/event_source/devices/uprobe/type")?.trim().parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "cant read the uprobe pmu type"))?;
+        let path = CString::new(target).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path has a nul byte in it"))?;
+
+        let attr = PerfEventAttr {
+            event_type: pmu_type,
+            size: size_of::<PerfEventAttr>() as u32,
+            config: note.semaphore_offset.unwrap_or(0) << UPROBE_REF_CTR_SHIFT,
+            sample_period: 1,
+            wakeup_events: 1,
+            config1: path.as_ptr() as u64,
+            config2: note.offset,
+            ..Default::default()
+        };
+        // a uprobe for every process still needs some cpu, it fires on all of them anyway
+        let event = match pid {
+            Some(pid) => perf_event::perf_event_open(&attr, pid, -1)?,
+            None => perf_event::perf_event_open(&attr, -1, 0)?,
+        };
+
+        let link = perf_event::attach_program(program, &event, cookie)?;
+
+        debug!("attached usdt probe {}:{} at {:#x} in {target}", note.provider, note.name, note.offset);
+        Ok(UsdtProbe { _perf_event: event, _link: link })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // built from testdata/usdt.c, see the top of that file
+    const FIXTURE: &[u8] = include_bytes!("../testdata/usdt.so");
+
+    #[test]
+    fn reads_notes() {
+        let notes = parse_notes(FIXTURE).unwrap();
+        assert_eq!(notes.len(), 2);
+
+        assert_eq!((notes[0].provider.as_str(), notes[0].name.as_str()), ("fixture", "query"));
+        assert_eq!(notes[0].offset, 0x29b);
+        assert_eq!(notes[0].semaphore_offset, Some(0x1002));
+        let specs: Vec<&str> = notes[0].args.iter().map(|(spec, _)| spec.as_str()).collect();
+        assert_eq!(specs, ["-4@%edi", "8@-16(%rbp)", "8@counter+8(%rip)", "4@$42"]);
+
+        assert_eq!(notes[1].name, "tick");
+        assert_eq!(notes[1].semaphore_offset, Some(0x1000));
+        assert!(notes[1].args.is_empty());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn resolves_rip_relative_symbols() {
+        let notes = parse_notes(FIXTURE).unwrap();
+        let counter = notes[0].args[2].1.unwrap();
+        assert_eq!((counter.kind, counter.size, counter.reg_offset), (USDT_ARG_DEREF, 8, 16 * 8));
+        // counter is at 0x2008 and the probe at 0x29b
+        assert_eq!(counter.value, 0x2010 - 0x29b);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn parses_arg_specs() {
+        let no_symbols = |_: &str| None;
+        let parse = |spec| parse_arg_spec(spec, 0x1000, no_symbols).map(|arg| (arg.kind, arg.size, arg.reg_offset, arg.value));
+
+        assert_eq!(parse("-4@%edi"), Some((USDT_ARG_REG, -4, 14 * 8, 0)));
+        assert_eq!(parse("8@-16(%rbp)"), Some((USDT_ARG_DEREF, 8, 4 * 8, -16)));
+        assert_eq!(parse("8@(%rax)"), Some((USDT_ARG_DEREF, 8, 10 * 8, 0)));
+        assert_eq!(parse("4@$42"), Some((USDT_ARG_CONST, 4, 0, 42)));
+        assert_eq!(parse("-8@$-1"), Some((USDT_ARG_CONST, -8, 0, -1)));
+
+        let symbols = |name: &str| (name == "counter").then_some(0x3000);
+        let parse = |spec| parse_arg_spec(spec, 0x1000, symbols).map(|arg| arg.value);
+        assert_eq!(parse("8@counter(%rip)"), Some(0x2000));
+        assert_eq!(parse("8@counter-8(%rip)"), Some(0x2000 - 8));
+
+        // things we cant fetch say so instead of reading as 0
+        assert_eq!(parse("8@missing(%rip)"), None);
+        assert_eq!(parse("8@counter(%rax)"), None);
+        assert_eq!(parse("8@%xmm0"), None);
+        assert_eq!(parse("8@(%rax,%rbx,8)"), None);
+        assert_eq!(parse("nonsense"), None);
+    }
+}
@@ -0,0 +1,39 @@
+// fixture for the usdt tests, sys/sdt.h isnt needed since this writes the notes the way it would
+//
+//   gcc -O1 -shared -fPIC -nostdlib -s -Wl,-z,max-page-size=4096 -Wl,-z,noseparate-code -Wl,--build-id=none -o usdt.so usdt.c
+
+#define STAPSDT_NOTE(provider, name, args)                                          \
+    __asm__ volatile("990: nop\n"                                                   \
+                     ".pushsection .note.stapsdt,\"?\",\"note\"\n"                  \
+                     ".balign 4\n"                                                  \
+                     ".4byte 992f-991f, 994f-993f, 3\n"                             \
+                     "991: .asciz \"stapsdt\"\n"                                    \
+                     "992: .balign 4\n"                                             \
+                     "993: .8byte 990b\n"                                           \
+                     ".8byte _.stapsdt.base\n"                                      \
+                     ".8byte " #provider "_" #name "_semaphore\n"                   \
+                     ".asciz \"" #provider "\"\n"                                   \
+                     ".asciz \"" #name "\"\n"                                       \
+                     ".asciz \"" args "\"\n"                                        \
+                     "994: .balign 4\n"                                             \
+                     ".popsection\n"                                                \
+                     ".ifndef _.stapsdt.base\n"                                     \
+                     ".pushsection .stapsdt.base,\"aG\",\"progbits\",.stapsdt.base,comdat\n" \
+                     ".weak _.stapsdt.base\n"                                       \
+                     ".hidden _.stapsdt.base\n"                                     \
+                     "_.stapsdt.base: .space 1\n"                                   \
+                     ".size _.stapsdt.base, 1\n"                                    \
+                     ".popsection\n"                                                \
+                     ".endif\n")
+
+unsigned short fixture_query_semaphore __attribute__((section(".probes"))) = 0;
+unsigned short fixture_tick_semaphore __attribute__((section(".probes"))) = 0;
+long counter = 0;
+
+void query(void) {
+    STAPSDT_NOTE(fixture, query, "-4@%edi 8@-16(%rbp) 8@counter+8(%rip) 4@$42");
+}
+
+void tick(void) {
+    STAPSDT_NOTE(fixture, tick, "");
+}